@@ -0,0 +1,168 @@
+use std::cmp;
+use std::mem::size_of;
+use std::ptr;
+use allocator::OwnedAllocator;
+use allocator::ShareableAllocator;
+use util::PowerOfTwo;
+#[cfg(test)]
+use checked_alloc::CheckedAlloc;
+#[cfg(test)]
+use heap_alloc::HeapAlloc;
+#[cfg(test)]
+use rand;
+#[cfg(test)]
+use rand::Rng;
+#[cfg(test)]
+use simple_alloc::BlockAlloc;
+// Serves any alignment on top of an allocator that only guarantees base_align. Larger alignments over-allocate
+// by align + size_of::<usize>() and store the distance back to the inner pointer just before the returned one.
+pub struct AlignedAlloc<A: OwnedAllocator> {
+    allocator: A,
+    base_align: PowerOfTwo,
+}
+impl<A: OwnedAllocator> AlignedAlloc<A> {
+    pub fn new(allocator: A, base_align: PowerOfTwo) -> Self {
+        return AlignedAlloc {
+            allocator: allocator,
+            base_align: base_align,
+        };
+    }
+    fn upgraded(&self, align: PowerOfTwo) -> bool {
+        return align > self.base_align;
+    }
+    fn extra(align: PowerOfTwo) -> usize {
+        return align.into() + size_of::<usize>();
+    }
+    unsafe fn read_offset(ptr: *mut u8) -> usize {
+        let mut offset = 0usize;
+        ptr::copy_nonoverlapping(ptr.offset(-(size_of::<usize>() as isize)),
+                                 &mut offset as *mut usize as *mut u8,
+                                 size_of::<usize>());
+        return offset;
+    }
+    unsafe fn place(raw: *mut u8, align: PowerOfTwo) -> (*mut u8, usize) {
+        let ret = align.align_ptr_mut(raw.offset(size_of::<usize>() as isize));
+        let offset = (ret as usize) - (raw as usize);
+        ptr::copy_nonoverlapping(&offset as *const usize as *const u8,
+                                 ret.offset(-(size_of::<usize>() as isize)),
+                                 size_of::<usize>());
+        return (ret, offset);
+    }
+}
+impl<A: OwnedAllocator + Default> Default for AlignedAlloc<A> {
+    fn default() -> Self {
+        return Self::new(A::default(), PowerOfTwo::new(1));
+    }
+}
+unsafe impl<A: OwnedAllocator> OwnedAllocator for AlignedAlloc<A> {
+    unsafe fn allocate(&mut self, size: usize, align: PowerOfTwo) -> *mut u8 {
+        if !self.upgraded(align) {
+            return self.allocator.allocate(size, align);
+        }
+        let total = match size.checked_add(Self::extra(align)) {
+            Some(total) => total,
+            None => return ptr::null_mut(),
+        };
+        let raw = self.allocator.allocate(total, self.base_align);
+        if raw.is_null() {
+            return raw;
+        }
+        return Self::place(raw, align).0;
+    }
+    unsafe fn reallocate(&mut self, ptr: *mut u8, old_size: usize, new_size: usize, align: PowerOfTwo) -> *mut u8 {
+        if !self.upgraded(align) {
+            return self.allocator.reallocate(ptr, old_size, new_size, align);
+        }
+        let total = match new_size.checked_add(Self::extra(align)) {
+            Some(total) => total,
+            None => return ptr::null_mut(),
+        };
+        let old_offset = Self::read_offset(ptr);
+        let raw = ptr.offset(-(old_offset as isize));
+        let new_raw = self.allocator.reallocate(raw, old_size + Self::extra(align), total, self.base_align);
+        if new_raw.is_null() {
+            return new_raw;
+        }
+        // The inner allocator moved the block, so the aligned address may now sit at a different offset.
+        let data = new_raw.offset(old_offset as isize);
+        let new_offset = (align.align_ptr_mut(new_raw.offset(size_of::<usize>() as isize)) as usize) -
+                         (new_raw as usize);
+        if new_offset != old_offset {
+            ptr::copy(data, new_raw.offset(new_offset as isize), cmp::min(old_size, new_size));
+        }
+        return Self::place(new_raw, align).0;
+    }
+    unsafe fn reallocate_inplace(&mut self,
+                                 ptr: *mut u8,
+                                 old_size: usize,
+                                 new_size: usize,
+                                 align: PowerOfTwo)
+                                 -> usize {
+        if !self.upgraded(align) {
+            return self.allocator.reallocate_inplace(ptr, old_size, new_size, align);
+        }
+        let raw = ptr.offset(-(Self::read_offset(ptr) as isize));
+        let extra = Self::extra(align);
+        return self.allocator.reallocate_inplace(raw, old_size + extra, new_size + extra, self.base_align) - extra;
+    }
+    unsafe fn deallocate(&mut self, ptr: *mut u8, old_size: usize, align: PowerOfTwo) {
+        if !self.upgraded(align) {
+            return self.allocator.deallocate(ptr, old_size, align);
+        }
+        let raw = ptr.offset(-(Self::read_offset(ptr) as isize));
+        self.allocator.deallocate(raw, old_size + Self::extra(align), self.base_align);
+    }
+    unsafe fn extendable_size(&self, ptr: *mut u8, old_size: usize, align: PowerOfTwo) -> usize {
+        if !self.upgraded(align) {
+            return self.allocator.extendable_size(ptr, old_size, align);
+        }
+        let raw = ptr.offset(-(Self::read_offset(ptr) as isize));
+        let extra = Self::extra(align);
+        return cmp::max(old_size,
+                        self.allocator.extendable_size(raw, old_size + extra, self.base_align).saturating_sub(extra));
+    }
+    unsafe fn usable_size(&self, size: usize, align: PowerOfTwo) -> usize {
+        if !self.upgraded(align) {
+            return self.allocator.usable_size(size, align);
+        }
+        return size;
+    }
+}
+unsafe impl<A: ShareableAllocator> ShareableAllocator for AlignedAlloc<A> {}
+#[test]
+fn aligned_random_test() {
+    unsafe {
+        let mut rng = rand::XorShiftRng::new_unseeded();
+        let mut alloc = CheckedAlloc::new(AlignedAlloc::new(CheckedAlloc::new(HeapAlloc, Default::default()),
+                                                            PowerOfTwo::new(1)),
+                                          Default::default());
+        for _ in 0..100 {
+            let align = PowerOfTwo::new(1 << rng.gen_range(0, 13));
+            let mut size = rng.gen_range(1, 256);
+            let mut ptr = alloc.allocate(size, align);
+            *ptr = 42;
+            for _ in 0..5 {
+                let new_size = rng.gen_range(1, 256);
+                if rng.gen_weighted_bool(2) {
+                    ptr = alloc.reallocate(ptr, size, new_size, align);
+                    size = new_size;
+                } else {
+                    size = alloc.reallocate_inplace(ptr, size, new_size, align);
+                }
+                assert_eq!(42, *ptr);
+            }
+            alloc.deallocate(ptr, size, align);
+        }
+    }
+}
+#[test]
+fn aligned_block_test() {
+    unsafe {
+        let mut alloc = AlignedAlloc::new(BlockAlloc::new(HeapAlloc, 4096), PowerOfTwo::new(1));
+        alloc.allocate(1, PowerOfTwo::new(1));
+        for &align in &[2, 8, 64, 256] {
+            let align = PowerOfTwo::new(align);
+            assert!(align.is_aligned_ptr_mut(alloc.allocate(3, align)));
+        }
+    }
+}
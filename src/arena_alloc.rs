@@ -5,6 +5,10 @@ use util;
 use util::PowerOfTwo;
 use std::mem;
 use std::ptr;
+use std::cell::UnsafeCell;
+use std::slice;
+use std::vec;
+use alloc::oom;
 use allocator::OwnedAllocator;
 #[cfg(test)]
 use heap_alloc::HeapAlloc;
@@ -18,18 +22,62 @@ use checked_alloc::CheckedAlloc;
 use checked_alloc::CheckedAllocOptions;
 #[cfg(test)]
 use simple_alloc::LoggingAlloc;
+#[cfg(test)]
+use simple_alloc::MockAlloc;
+#[cfg(test)]
+use simple_alloc::MockAllocResult;
+#[cfg(test)]
+use std::sync::Arc;
+#[cfg(test)]
+use std::sync::atomic::AtomicUsize;
+#[cfg(test)]
+use std::sync::atomic::Ordering;
+const MAX_TAILS: usize = 16;
 fn arena_heap_alignment() -> PowerOfTwo {
     return PowerOfTwo::new(1);
 }
+pub trait ArenaGrowthPolicy {
+    fn next_block_size(&self, old_size: usize) -> usize;
+}
+pub struct GeometricGrowth(pub usize);
+impl ArenaGrowthPolicy for GeometricGrowth {
+    fn next_block_size(&self, old_size: usize) -> usize {
+        return old_size.saturating_mul(self.0);
+    }
+}
+pub struct LinearGrowth(pub usize);
+impl ArenaGrowthPolicy for LinearGrowth {
+    fn next_block_size(&self, old_size: usize) -> usize {
+        return old_size.saturating_add(self.0);
+    }
+}
+pub struct CappedGrowth<P: ArenaGrowthPolicy>(pub P, pub usize);
+impl<P: ArenaGrowthPolicy> ArenaGrowthPolicy for CappedGrowth<P> {
+    fn next_block_size(&self, old_size: usize) -> usize {
+        return cmp::min(self.0.next_block_size(old_size), self.1);
+    }
+}
 pub struct ArenaOptions {
     pub start_block_size: usize,
     pub recommended_max_block_size: usize,
+    // Requests larger than this that do not fit the live block get a block of their own.
+    pub large_object_threshold: usize,
+    // Sizes each new block from the one before. None doubles the size up to recommended_max_block_size, which
+    // otherwise only bounds the start block; a policy's sizes are used as given.
+    pub growth: Option<Box<ArenaGrowthPolicy + Send>>,
+    // Allocations that would take more than this many bytes from the backing allocator return null.
+    pub max_total_bytes: usize,
+    pub on_exhausted: Option<Box<Fn(usize) + Send>>,
 }
 impl Default for ArenaOptions {
     fn default() -> ArenaOptions {
         return ArenaOptions {
             start_block_size: 4096,
             recommended_max_block_size: 65536,
+            large_object_threshold: 16384,
+            growth: None,
+            max_total_bytes: usize::max_value(),
+            on_exhausted: None,
         };
     }
 }
@@ -37,6 +85,25 @@ struct UsedBlock {
     memory: *mut u8,
     size: usize,
 }
+// A block that was live once; bytes between next and the end of the block were never handed out from it, though
+// some may since have been reused as a tail.
+struct RetiredBlock {
+    block: UsedBlock,
+    next: *mut u8,
+}
+struct LargeBlock {
+    block: UsedBlock,
+    size: usize,
+    // Bytes skipped at the front of the block to align the allocation.
+    padding: usize,
+    // Increases with every large allocation, so a mark can tell which blocks came after it even once older ones
+    // have been freed from the middle of the list.
+    generation: usize,
+}
+struct Tail {
+    begin: *mut u8,
+    end: *mut u8,
+}
 struct LiveBlock {
     begin: *mut u8,
     next: *mut u8,
@@ -52,6 +119,21 @@ impl UsedBlock {
     unsafe fn destroy<A: OwnedAllocator>(self, allocator: &mut A) {
         allocator.deallocate(self.memory, self.size, arena_heap_alignment());
     }
+    fn contains(&self, ptr: *mut u8) -> bool {
+        return self.memory <= ptr && (ptr as usize) < (self.memory as usize) + self.size;
+    }
+    fn reopen(self, next: *mut u8) -> LiveBlock {
+        return LiveBlock {
+            begin: self.memory,
+            next: next,
+            end: unsafe { self.memory.offset(self.size as isize) },
+        };
+    }
+}
+impl Tail {
+    fn size(&self) -> usize {
+        return util::distance(self.begin, self.end);
+    }
 }
 impl LiveBlock {
     fn new() -> Self {
@@ -70,25 +152,29 @@ impl LiveBlock {
             *self = LiveBlock::new();
         }
     }
+    fn size(&self) -> usize {
+        return util::distance(self.begin, self.end);
+    }
     unsafe fn try_ensure_end<A: OwnedAllocator>(&mut self,
                                                 allocator: &mut A,
-                                                options: &ArenaOptions,
+                                                max_block_size: usize,
+                                                budget: usize,
                                                 new_end: usize)
                                                 -> bool {
         if new_end <= self.end as usize {
             return true;
         } else {
+            let old_size = self.size();
             let needed_size = new_end - (self.begin as usize);
-            let mut new_size = (needed_size + 1).next_power_of_two();
-            new_size = cmp::min(new_size, options.recommended_max_block_size);
+            let mut new_size = (needed_size + 1).checked_next_power_of_two().unwrap_or(usize::max_value());
+            new_size = cmp::min(new_size, max_block_size);
+            new_size = cmp::min(new_size, old_size.saturating_add(budget));
             if new_size < needed_size {
                 return false;
             }
-            new_size = allocator.usable_size(new_size, arena_heap_alignment());
-            let real_new_size = allocator.reallocate_inplace(self.begin,
-                                                             util::distance(self.begin, self.end),
-                                                             new_size,
-                                                             arena_heap_alignment());
+            new_size = cmp::min(allocator.usable_size(new_size, arena_heap_alignment()),
+                                old_size.saturating_add(budget));
+            let real_new_size = allocator.reallocate_inplace(self.begin, old_size, new_size, arena_heap_alignment());
             self.end = self.begin.offset(real_new_size as isize);
             return real_new_size == new_size;
         }
@@ -98,15 +184,17 @@ impl LiveBlock {
     }
     unsafe fn initialize<A: OwnedAllocator>(&mut self,
                                             allocator: &mut A,
-                                            options: &ArenaOptions,
+                                            budget: usize,
                                             needed_size: usize,
                                             recommended_size: usize,
                                             align: PowerOfTwo)
                                             -> bool {
-        let actual_needed_size = needed_size + align.into() - 1;
-        let actual_recommended_size = cmp::min(options.recommended_max_block_size, recommended_size);
-        let mut new_size = cmp::max(actual_needed_size, actual_recommended_size);
-        new_size = allocator.usable_size(new_size, arena_heap_alignment());
+        let actual_needed_size = match needed_size.checked_add(align.into() - 1) {
+            Some(actual_needed_size) if actual_needed_size <= budget => actual_needed_size,
+            _ => return false,
+        };
+        let mut new_size = cmp::max(actual_needed_size, recommended_size);
+        new_size = cmp::min(allocator.usable_size(new_size, arena_heap_alignment()), budget);
         self.begin = allocator.allocate(new_size, arena_heap_alignment());
         if self.begin.is_null() {
             return false;
@@ -117,66 +205,617 @@ impl LiveBlock {
     }
     unsafe fn try_allocate<A: OwnedAllocator>(&mut self,
                                               allocator: &mut A,
-                                              options: &ArenaOptions,
+                                              max_block_size: usize,
+                                              budget: usize,
                                               size: usize,
                                               align: PowerOfTwo)
                                               -> *mut u8 {
         let aligned_next = align.align_size(self.next as usize);
-        if self.try_ensure_end(allocator, options, aligned_next + size) {
-            self.next = (aligned_next + size) as *mut u8;
+        let new_end = match aligned_next.checked_add(size) {
+            Some(new_end) => new_end,
+            None => return null_mut(),
+        };
+        if self.try_ensure_end(allocator, max_block_size, budget, new_end) {
+            self.next = new_end as *mut u8;
             return aligned_next as *mut u8;
         } else {
             return null_mut();
         }
     }
 }
-pub struct Arena<A: OwnedAllocator> {
+pub struct ArenaMark {
+    used: usize,
+    large: usize,
+    begin: *mut u8,
+    next: *mut u8,
+    allocated_bytes: usize,
+    padding_bytes: usize,
+    wasted_bytes: usize,
+    // Parts of allocated_bytes and padding_bytes held in large blocks at the mark.
+    large_allocated_bytes: usize,
+    large_padding_bytes: usize,
+}
+// Byte counts describe what the arena holds now, except recovered_bytes which counts over its whole life.
+#[derive(Clone, Copy, Debug)]
+pub struct ArenaStats {
+    pub reserved_bytes: usize,
+    pub allocated_bytes: usize,
+    pub padding_bytes: usize,
+    pub wasted_bytes: usize,
+    pub recovered_bytes: usize,
+    pub block_count: usize,
+    pub largest_block: usize,
+}
+// One block held from the backing allocator; bytes between next and end have not been handed out.
+#[derive(Clone, Copy, Debug)]
+pub struct ArenaBlock {
+    pub begin: *mut u8,
+    pub next: *mut u8,
+    pub end: *mut u8,
+}
+struct ArenaState<A: OwnedAllocator> {
     allocator: A,
     options: ArenaOptions,
-    used: Vec<UsedBlock>,
+    used: Vec<RetiredBlock>,
     live: LiveBlock,
+    // Empty blocks kept for reuse by reset and rollback, sorted by size.
+    spare: Vec<UsedBlock>,
+    // Unused ends of blocks in used, sorted by size.
+    tails: Vec<Tail>,
+    // Dedicated blocks holding one large object each.
+    large: Vec<LargeBlock>,
+    next_large_generation: usize,
+    recovered_bytes: usize,
+    // Bytes currently held from the backing allocator.
+    reserved_bytes: usize,
+    allocated_bytes: usize,
+    padding_bytes: usize,
+    // Bytes left at the ends of retired blocks and not yet recovered.
+    wasted_bytes: usize,
 }
-impl<A: OwnedAllocator> Arena<A> {
-    pub fn new(allocator: A, options: ArenaOptions) -> Self {
+impl<A: OwnedAllocator> ArenaState<A> {
+    fn new(allocator: A, options: ArenaOptions) -> Self {
         assert!(options.start_block_size > 0);
         assert!(options.recommended_max_block_size > 0);
-        return Arena {
+        return ArenaState {
             allocator: allocator,
             options: options,
             used: Vec::new(),
             live: LiveBlock::new(),
+            spare: Vec::new(),
+            tails: Vec::new(),
+            large: Vec::new(),
+            next_large_generation: 0,
+            recovered_bytes: 0,
+            reserved_bytes: 0,
+            allocated_bytes: 0,
+            padding_bytes: 0,
+            wasted_bytes: 0,
+        };
+    }
+    fn recovered_bytes(&self) -> usize {
+        return self.recovered_bytes;
+    }
+    fn reserved_bytes(&self) -> usize {
+        return self.reserved_bytes;
+    }
+    fn stats(&self) -> ArenaStats {
+        let blocks = self.blocks();
+        let largest_block = blocks.iter()
+            .fold(0, |largest, block| cmp::max(largest, util::distance(block.begin, block.end)));
+        return ArenaStats {
+            reserved_bytes: self.reserved_bytes,
+            allocated_bytes: self.allocated_bytes,
+            padding_bytes: self.padding_bytes,
+            wasted_bytes: self.wasted_bytes,
+            recovered_bytes: self.recovered_bytes,
+            block_count: blocks.len(),
+            largest_block: largest_block,
+        };
+    }
+    fn blocks(&self) -> Vec<ArenaBlock> {
+        let mut blocks = Vec::new();
+        for &RetiredBlock { ref block, next } in &self.used {
+            blocks.push(ArenaBlock {
+                begin: block.memory,
+                next: next,
+                end: unsafe { block.memory.offset(block.size as isize) },
+            });
+        }
+        if self.live.initialized() {
+            blocks.push(ArenaBlock {
+                begin: self.live.begin,
+                next: self.live.next,
+                end: self.live.end,
+            });
+        }
+        for block in &self.spare {
+            blocks.push(ArenaBlock {
+                begin: block.memory,
+                next: block.memory,
+                end: unsafe { block.memory.offset(block.size as isize) },
+            });
+        }
+        for large in &self.large {
+            blocks.push(ArenaBlock {
+                begin: large.block.memory,
+                next: unsafe { large.block.memory.offset((large.padding + large.size) as isize) },
+                end: unsafe { large.block.memory.offset(large.block.size as isize) },
+            });
+        }
+        return blocks;
+    }
+    fn retire_tail(&mut self, begin: *mut u8, end: *mut u8) {
+        let tail = Tail {
+            begin: begin,
+            end: end,
+        };
+        if tail.size() == 0 {
+            return;
+        }
+        self.wasted_bytes += tail.size();
+        if self.tails.len() == MAX_TAILS {
+            if self.tails[0].size() >= tail.size() {
+                return;
+            }
+            self.tails.remove(0);
+        }
+        let index = match self.tails.binary_search_by(|other| other.size().cmp(&tail.size())) {
+            Ok(index) | Err(index) => index,
+        };
+        self.tails.insert(index, tail);
+    }
+    fn allocate_from_tails(&mut self, size: usize, align: PowerOfTwo) -> *mut u8 {
+        for index in 0..self.tails.len() {
+            let aligned = align.align_size(self.tails[index].begin as usize);
+            let end = match aligned.checked_add(size) {
+                Some(end) => end,
+                None => return null_mut(),
+            };
+            if end <= self.tails[index].end as usize {
+                let tail = self.tails.remove(index);
+                self.recovered_bytes += size;
+                self.allocated_bytes += size;
+                self.padding_bytes += aligned - tail.begin as usize;
+                self.wasted_bytes -= tail.size();
+                self.retire_tail(end as *mut u8, tail.end);
+                if let Some(retired) = self.used.iter_mut().find(|retired| retired.block.contains(tail.begin)) {
+                    retired.next = end as *mut u8;
+                }
+                return aligned as *mut u8;
+            }
+        }
+        return null_mut();
+    }
+    // The largest a block of old_size may be extended to in place, or the size of the block after it.
+    fn max_block_size(&self, old_size: usize) -> usize {
+        match self.options.growth {
+            Some(ref growth) => return growth.next_block_size(old_size),
+            None => return self.options.recommended_max_block_size,
+        }
+    }
+    fn budget(&self) -> usize {
+        return self.options.max_total_bytes.saturating_sub(self.reserved_bytes);
+    }
+    fn exhausted(&self, size: usize) -> *mut u8 {
+        if let Some(ref on_exhausted) = self.options.on_exhausted {
+            on_exhausted(size);
+        }
+        return null_mut();
+    }
+    unsafe fn try_allocate_live(&mut self, size: usize, align: PowerOfTwo) -> *mut u8 {
+        let old_size = self.live.size();
+        let old_next = self.live.next;
+        let budget = self.budget();
+        let max_block_size = self.max_block_size(old_size);
+        let result = self.live.try_allocate(&mut self.allocator, max_block_size, budget, size, align);
+        self.reserved_bytes += self.live.size() - old_size;
+        if !result.is_null() {
+            self.allocated_bytes += size;
+            self.padding_bytes += util::distance(old_next, result);
+        }
+        return result;
+    }
+    unsafe fn allocate_large(&mut self, size: usize, align: PowerOfTwo) -> *mut u8 {
+        let block_size = match size.checked_add(align.into() - 1) {
+            Some(block_size) if block_size <= self.budget() => block_size,
+            _ => return self.exhausted(size),
+        };
+        let memory = self.allocator.allocate(block_size, arena_heap_alignment());
+        if memory.is_null() {
+            return memory;
+        }
+        let ret = align.align_ptr_mut(memory);
+        let padding = util::distance(memory, ret);
+        self.reserved_bytes += block_size;
+        self.allocated_bytes += size;
+        self.padding_bytes += padding;
+        self.large.push(LargeBlock {
+            block: UsedBlock::new(memory, block_size),
+            size: size,
+            padding: padding,
+            generation: self.next_large_generation,
+        });
+        self.next_large_generation += 1;
+        return ret;
+    }
+    // Frees every large block allocated at or after the given generation.
+    fn release_large(&mut self, from: usize) {
+        let keep = self.large.iter().position(|large| large.generation >= from).unwrap_or(self.large.len());
+        for large in self.large.drain(keep..) {
+            self.reserved_bytes -= large.block.size;
+            unsafe {
+                large.block.destroy(&mut self.allocator);
+            }
+        }
+    }
+    fn large_bytes(&self) -> (usize, usize) {
+        return self.large
+            .iter()
+            .fold((0, 0), |(allocated, padding), large| {
+                (allocated + large.size, padding + large.padding)
+            });
+    }
+    fn forget_tails(&mut self) {
+        let used = &self.used;
+        self.tails.retain(|tail| used.iter().any(|retired| retired.block.contains(tail.begin)));
+    }
+    fn retain(&mut self, block: UsedBlock) {
+        let index = match self.spare.binary_search_by(|other| other.size.cmp(&block.size)) {
+            Ok(index) | Err(index) => index,
         };
+        self.spare.insert(index, block);
+    }
+    fn retain_live(&mut self) {
+        let live = mem::replace(&mut self.live, LiveBlock::new());
+        if live.initialized() {
+            self.retain(unsafe { live.close() });
+        }
+    }
+    fn reopen_spare(&mut self, padded_size: usize) -> bool {
+        match self.spare.last() {
+            Some(block) if block.size >= padded_size => {}
+            _ => return false,
+        }
+        let block = self.spare.pop().unwrap();
+        let memory = block.memory;
+        self.live = block.reopen(memory);
+        return true;
+    }
+    fn reset(&mut self) {
+        self.tails.clear();
+        self.allocated_bytes = 0;
+        self.padding_bytes = 0;
+        self.wasted_bytes = 0;
+        self.release_large(0);
+        self.retain_live();
+        let used = mem::replace(&mut self.used, Vec::new());
+        for retired in used {
+            self.retain(retired.block);
+        }
+    }
+    fn trim(&mut self, keep_bytes: usize) {
+        let mut kept = self.spare.iter().fold(0, |total, block| total + block.size);
+        while kept > keep_bytes && !self.spare.is_empty() {
+            let block = self.spare.remove(0);
+            kept -= block.size;
+            self.reserved_bytes -= block.size;
+            unsafe {
+                block.destroy(&mut self.allocator);
+            }
+        }
+    }
+    fn checkpoint(&self) -> ArenaMark {
+        let (large_allocated_bytes, large_padding_bytes) = self.large_bytes();
+        return ArenaMark {
+            used: self.used.len(),
+            large: self.next_large_generation,
+            begin: self.live.begin,
+            next: self.live.next,
+            allocated_bytes: self.allocated_bytes,
+            padding_bytes: self.padding_bytes,
+            wasted_bytes: self.wasted_bytes,
+            large_allocated_bytes: large_allocated_bytes,
+            large_padding_bytes: large_padding_bytes,
+        };
+    }
+    fn rollback(&mut self, mark: ArenaMark) {
+        assert!(mark.used <= self.used.len(), "Arena: rollback to a stale mark");
+        self.release_large(mark.large);
+        // Large blocks from before the mark may have been freed since; count only the ones still held.
+        let (large_allocated_bytes, large_padding_bytes) = self.large_bytes();
+        self.allocated_bytes = mark.allocated_bytes - mark.large_allocated_bytes + large_allocated_bytes;
+        self.padding_bytes = mark.padding_bytes - mark.large_padding_bytes + large_padding_bytes;
+        self.wasted_bytes = mark.wasted_bytes;
+        if mark.begin.is_null() {
+            let released: Vec<UsedBlock> = self.used.drain(mark.used..).map(|retired| retired.block).collect();
+            for block in released {
+                self.retain(block);
+            }
+            self.retain_live();
+            self.forget_tails();
+            return;
+        }
+        if self.used.len() > mark.used {
+            // The block that was live at the mark has been retired since: everything newer is kept as spare.
+            self.retain_live();
+            let released: Vec<UsedBlock> = self.used.drain(mark.used + 1..).map(|retired| retired.block).collect();
+            for block in released {
+                self.retain(block);
+            }
+            self.live = self.used.pop().unwrap().block.reopen(mark.next);
+            self.forget_tails();
+        }
+        assert!(self.live.begin == mark.begin, "Arena: rollback to a stale mark");
+        self.live.next = mark.next;
     }
 }
+// The state lives in an UnsafeCell so that the typed allocation methods can bump through &self while earlier
+// results are still borrowed. Everything that invalidates allocations takes &mut self.
+pub struct Arena<A: OwnedAllocator> {
+    state: UnsafeCell<ArenaState<A>>,
+}
+impl<A: OwnedAllocator> Arena<A> {
+    pub fn new(allocator: A, options: ArenaOptions) -> Self {
+        return Arena { state: UnsafeCell::new(ArenaState::new(allocator, options)) };
+    }
+    fn state(&self) -> &ArenaState<A> {
+        return unsafe { &*self.state.get() };
+    }
+    fn state_mut(&mut self) -> &mut ArenaState<A> {
+        return unsafe { &mut *self.state.get() };
+    }
+    pub fn recovered_bytes(&self) -> usize {
+        return self.state().recovered_bytes();
+    }
+    pub fn reserved_bytes(&self) -> usize {
+        return self.state().reserved_bytes();
+    }
+    pub fn stats(&self) -> ArenaStats {
+        return self.state().stats();
+    }
+    pub fn blocks(&self) -> vec::IntoIter<ArenaBlock> {
+        return self.state().blocks().into_iter();
+    }
+    pub fn reset(&mut self) {
+        self.state_mut().reset();
+    }
+    pub fn trim(&mut self, keep_bytes: usize) {
+        self.state_mut().trim(keep_bytes);
+    }
+    pub fn checkpoint(&self) -> ArenaMark {
+        return self.state().checkpoint();
+    }
+    pub fn rollback(&mut self, mark: ArenaMark) {
+        self.state_mut().rollback(mark);
+    }
+    pub fn scope<R, F>(&mut self, f: F) -> R
+        where F: FnOnce(&mut Self) -> R
+    {
+        let mark = self.checkpoint();
+        let ret = f(self);
+        self.rollback(mark);
+        return ret;
+    }
+    pub fn child(&mut self) -> Arena<ChildAlloc<A>> {
+        return self.child_with_options(ArenaOptions::default());
+    }
+    pub fn child_with_options(&mut self, options: ArenaOptions) -> Arena<ChildAlloc<A>> {
+        let mark = self.checkpoint();
+        return Arena::new(ChildAlloc {
+                              parent: self,
+                              mark: Some(mark),
+                          },
+                          options);
+    }
+    fn try_alloc_raw(&self, size: usize, align: PowerOfTwo) -> Option<*mut u8> {
+        if size == 0 {
+            return Some(align.into() as *mut u8);
+        }
+        let ret = unsafe { (*self.state.get()).allocate(size, align) };
+        if ret.is_null() {
+            return None;
+        }
+        return Some(ret);
+    }
+    // Values are never dropped; see TypedArena for types that need their destructors run. The try_ variants return
+    // None when the backing allocator fails or max_total_bytes is reached; the others abort.
+    pub fn try_alloc<T>(&self, value: T) -> Option<&mut T> {
+        unsafe {
+            let ptr = match self.try_alloc_raw(mem::size_of::<T>(), PowerOfTwo::align_of::<T>()) {
+                Some(ptr) => ptr as *mut T,
+                None => return None,
+            };
+            ptr::write(ptr, value);
+            return Some(&mut *ptr);
+        }
+    }
+    pub fn alloc<T>(&self, value: T) -> &mut T {
+        match self.try_alloc(value) {
+            Some(ret) => return ret,
+            None => oom(),
+        }
+    }
+    pub fn try_alloc_slice_copy<T: Copy>(&self, values: &[T]) -> Option<&mut [T]> {
+        unsafe {
+            let size = mem::size_of::<T>().checked_mul(values.len()).expect("capacity overflow");
+            let ptr = match self.try_alloc_raw(size, PowerOfTwo::align_of::<T>()) {
+                Some(ptr) => ptr as *mut T,
+                None => return None,
+            };
+            ptr::copy_nonoverlapping(values.as_ptr(), ptr, values.len());
+            return Some(slice::from_raw_parts_mut(ptr, values.len()));
+        }
+    }
+    pub fn alloc_slice_copy<T: Copy>(&self, values: &[T]) -> &mut [T] {
+        match self.try_alloc_slice_copy(values) {
+            Some(ret) => return ret,
+            None => oom(),
+        }
+    }
+    pub fn try_alloc_str(&self, value: &str) -> Option<&mut str> {
+        return self.try_alloc_slice_copy(value.as_bytes())
+            .map(|bytes| unsafe { mem::transmute::<&mut [u8], &mut str>(bytes) });
+    }
+    pub fn alloc_str(&self, value: &str) -> &mut str {
+        match self.try_alloc_str(value) {
+            Some(ret) => return ret,
+            None => oom(),
+        }
+    }
+    pub fn try_alloc_from_iter<T, I: IntoIterator<Item = T>>(&self, iter: I) -> Option<&mut [T]> {
+        // Collected first: the iterator may itself allocate from this arena.
+        let mut values: Vec<T> = iter.into_iter().collect();
+        unsafe {
+            let size = mem::size_of::<T>().checked_mul(values.len()).expect("capacity overflow");
+            let ptr = match self.try_alloc_raw(size, PowerOfTwo::align_of::<T>()) {
+                Some(ptr) => ptr as *mut T,
+                None => return None,
+            };
+            ptr::copy_nonoverlapping(values.as_ptr(), ptr, values.len());
+            let len = values.len();
+            values.set_len(0);
+            return Some(slice::from_raw_parts_mut(ptr, len));
+        }
+    }
+    pub fn alloc_from_iter<T, I: IntoIterator<Item = T>>(&self, iter: I) -> &mut [T] {
+        match self.try_alloc_from_iter(iter) {
+            Some(ret) => return ret,
+            None => oom(),
+        }
+    }
+}
+unsafe impl<A: OwnedAllocator> OwnedAllocator for Arena<A> {
+    unsafe fn allocate(&mut self, size: usize, align: PowerOfTwo) -> *mut u8 {
+        return self.state_mut().allocate(size, align);
+    }
+    unsafe fn reallocate(&mut self, ptr: *mut u8, old_size: usize, new_size: usize, align: PowerOfTwo) -> *mut u8 {
+        return self.state_mut().reallocate(ptr, old_size, new_size, align);
+    }
+    unsafe fn reallocate_inplace(&mut self,
+                                 ptr: *mut u8,
+                                 old_size: usize,
+                                 new_size: usize,
+                                 align: PowerOfTwo)
+                                 -> usize {
+        return self.state_mut().reallocate_inplace(ptr, old_size, new_size, align);
+    }
+    unsafe fn deallocate(&mut self, ptr: *mut u8, old_size: usize, align: PowerOfTwo) {
+        self.state_mut().deallocate(ptr, old_size, align);
+    }
+    unsafe fn extendable_size(&self, ptr: *mut u8, old_size: usize, align: PowerOfTwo) -> usize {
+        return self.state().extendable_size(ptr, old_size, align);
+    }
+    unsafe fn usable_size(&self, size: usize, align: PowerOfTwo) -> usize {
+        return self.state().usable_size(size, align);
+    }
+}
+// Hands a child arena blocks carved out of its parent, and rolls the parent back when the child is dropped.
+pub struct ChildAlloc<'a, A: OwnedAllocator + 'a> {
+    parent: &'a mut Arena<A>,
+    mark: Option<ArenaMark>,
+}
+unsafe impl<'a, A: OwnedAllocator> OwnedAllocator for ChildAlloc<'a, A> {
+    unsafe fn allocate(&mut self, size: usize, align: PowerOfTwo) -> *mut u8 {
+        return self.parent.allocate(size, align);
+    }
+    unsafe fn reallocate(&mut self, ptr: *mut u8, old_size: usize, new_size: usize, align: PowerOfTwo) -> *mut u8 {
+        return self.parent.reallocate(ptr, old_size, new_size, align);
+    }
+    unsafe fn reallocate_inplace(&mut self,
+                                 ptr: *mut u8,
+                                 old_size: usize,
+                                 new_size: usize,
+                                 align: PowerOfTwo)
+                                 -> usize {
+        return self.parent.reallocate_inplace(ptr, old_size, new_size, align);
+    }
+    unsafe fn deallocate(&mut self, ptr: *mut u8, old_size: usize, align: PowerOfTwo) {
+        self.parent.deallocate(ptr, old_size, align);
+    }
+    unsafe fn extendable_size(&self, ptr: *mut u8, old_size: usize, align: PowerOfTwo) -> usize {
+        return self.parent.extendable_size(ptr, old_size, align);
+    }
+}
+impl<'a, A: OwnedAllocator> Drop for ChildAlloc<'a, A> {
+    fn drop(&mut self) {
+        if let Some(mark) = self.mark.take() {
+            self.parent.rollback(mark);
+        }
+    }
+}
+// The arena owns its blocks outright, so it can move between threads whenever its backing allocator can.
+unsafe impl<A: OwnedAllocator + Send> Send for Arena<A> {}
 impl<A: Default + OwnedAllocator> Default for Arena<A> {
     fn default() -> Self {
         return Arena::new(A::default(), ArenaOptions::default());
     }
 }
-unsafe impl<A: OwnedAllocator> OwnedAllocator for Arena<A> {
+unsafe impl<A: OwnedAllocator> OwnedAllocator for ArenaState<A> {
     unsafe fn deallocate(&mut self, ptr: *mut u8, old_size: usize, _align: PowerOfTwo) {
         if self.live.next == ptr.offset(old_size as isize) {
             self.live.next = ptr;
+            self.allocated_bytes -= old_size;
+        } else {
+            let index = self.large.iter().position(|large| large.block.contains(ptr));
+            if let Some(index) = index {
+                let large = self.large.remove(index);
+                self.reserved_bytes -= large.block.size;
+                self.allocated_bytes -= large.size;
+                self.padding_bytes -= large.padding;
+                large.block.destroy(&mut self.allocator);
+            }
         }
     }
     unsafe fn allocate(&mut self, size: usize, align: PowerOfTwo) -> *mut u8 {
-        let next_block_size;
         if self.live.initialized() {
-            let result = self.live.try_allocate(&mut self.allocator, &self.options, size, align);
-            if result.is_null() {
-                let old_block = mem::replace(&mut self.live, LiveBlock::new()).close();
-                next_block_size = (old_block.size + 1).next_power_of_two();
-                self.used.push(old_block);
-            } else {
+            let result = self.try_allocate_live(size, align);
+            if !result.is_null() {
                 return result;
             }
+        }
+        let result = self.allocate_from_tails(size, align);
+        if !result.is_null() {
+            return result;
+        }
+        if size > self.options.large_object_threshold {
+            return self.allocate_large(size, align);
+        }
+        // Room for the worst-case alignment padding; a size this close to usize::MAX can never be satisfied.
+        let padded_size = match size.checked_add(align.into() - 1) {
+            Some(padded_size) => padded_size,
+            None => return self.exhausted(size),
+        };
+        let next_block_size;
+        if self.live.initialized() {
+            let old_live = mem::replace(&mut self.live, LiveBlock::new());
+            self.retire_tail(old_live.next, old_live.end);
+            let next = old_live.next;
+            let old_block = old_live.close();
+            next_block_size = match self.options.growth {
+                Some(ref growth) => growth.next_block_size(old_block.size),
+                None => cmp::min(old_block.size.saturating_mul(2), self.options.recommended_max_block_size),
+            };
+            self.used.push(RetiredBlock {
+                block: old_block,
+                next: next,
+            });
+        } else if self.options.growth.is_none() {
+            next_block_size = cmp::min(self.options.start_block_size, self.options.recommended_max_block_size);
         } else {
             next_block_size = self.options.start_block_size;
         }
-        if !self.live.initialize(&mut self.allocator, &self.options, size, next_block_size, align) {
-            return null_mut();
+        if !self.reopen_spare(padded_size) {
+            if padded_size > self.budget() {
+                return self.exhausted(size);
+            }
+            let budget = self.budget();
+            if !self.live.initialize(&mut self.allocator, budget, size, next_block_size, align) {
+                return null_mut();
+            }
+            self.reserved_bytes += self.live.size();
         }
-        let result = self.live.try_allocate(&mut self.allocator, &self.options, size, align);
+        let result = self.try_allocate_live(size, align);
         assert!(!result.is_null());
         return result;
     }
@@ -185,7 +824,10 @@ unsafe impl<A: OwnedAllocator> OwnedAllocator for Arena<A> {
             return ptr;
         } else {
             let ret = self.allocate(new_size, align);
-            ptr::copy_nonoverlapping(ptr, ret, old_size);
+            if !ret.is_null() {
+                ptr::copy_nonoverlapping(ptr, ret, cmp::min(old_size, new_size));
+                self.deallocate(ptr, old_size, align);
+            }
             return ret;
         }
     }
@@ -196,8 +838,18 @@ unsafe impl<A: OwnedAllocator> OwnedAllocator for Arena<A> {
                                  _align: PowerOfTwo)
                                  -> usize {
         if self.live.next == ptr.offset(old_size as isize) {
-            if self.live.try_ensure_end(&mut self.allocator, &self.options, ptr as usize + new_size) {
+            let new_end = match (ptr as usize).checked_add(new_size) {
+                Some(new_end) => new_end,
+                None => return old_size,
+            };
+            let live_size = self.live.size();
+            let budget = self.budget();
+            let max_block_size = self.max_block_size(live_size);
+            let ensured = self.live.try_ensure_end(&mut self.allocator, max_block_size, budget, new_end);
+            self.reserved_bytes += self.live.size() - live_size;
+            if ensured {
                 self.live.next = ptr.offset(new_size as isize);
+                self.allocated_bytes = self.allocated_bytes + new_size - old_size;
                 return new_size;
             }
         }
@@ -215,12 +867,18 @@ unsafe impl<A: OwnedAllocator> OwnedAllocator for Arena<A> {
         return size;
     }
 }
-impl<A: OwnedAllocator> Drop for Arena<A> {
+impl<A: OwnedAllocator> Drop for ArenaState<A> {
     fn drop(&mut self) {
         unsafe {
-            for block in self.used.drain(..) {
+            for retired in self.used.drain(..) {
+                retired.block.destroy(&mut self.allocator);
+            }
+            for block in self.spare.drain(..) {
                 block.destroy(&mut self.allocator);
             }
+            for large in self.large.drain(..) {
+                large.block.destroy(&mut self.allocator);
+            }
             self.live.destroy(&mut self.allocator);
         }
     }
@@ -234,6 +892,7 @@ fn arena_random_test() {
             let arena_options = ArenaOptions {
                 start_block_size: 1,
                 recommended_max_block_size: 256,
+                ..ArenaOptions::default()
             };
             let outer_options = CheckedAllocOptions { ignore_leaks: true, ..Default::default() };
             let mut alloc = CheckedAlloc::new(Arena::new(LoggingAlloc::new(CheckedAlloc::new(HeapAlloc,
@@ -263,6 +922,277 @@ fn arena_random_test() {
         }
     }
 }
+#[test]
+fn arena_rollback_test() {
+    unsafe {
+        let arena_options = ArenaOptions {
+            start_block_size: 16,
+            recommended_max_block_size: 64,
+            ..ArenaOptions::default()
+        };
+        let mut arena = Arena::new(CheckedAlloc::new(HeapAlloc, Default::default()), arena_options);
+        let empty = arena.checkpoint();
+        arena.allocate(8, PowerOfTwo::new(1));
+        let mark = arena.checkpoint();
+        let first = arena.allocate(8, PowerOfTwo::new(1));
+        for _ in 0..20 {
+            arena.allocate(24, PowerOfTwo::new(4));
+        }
+        arena.rollback(mark);
+        assert_eq!(first, arena.scope(|arena| arena.allocate(8, PowerOfTwo::new(1))));
+        arena.scope(|arena| arena.allocate(100, PowerOfTwo::new(8)));
+        assert_eq!(first, arena.allocate(8, PowerOfTwo::new(1)));
+        arena.rollback(empty);
+        arena.allocate(8, PowerOfTwo::new(1));
+    }
+}
+#[test]
+fn arena_reset_test() {
+    unsafe {
+        let mut buffers = vec![[0u8; 64], [0u8; 64]];
+        let arena_options = ArenaOptions {
+            start_block_size: 64,
+            recommended_max_block_size: 64,
+            ..ArenaOptions::default()
+        };
+        let mut arena = Arena::new(MockAlloc::new(vec![MockAllocResult::Allocate(buffers[0].as_mut_ptr()),
+                                                       MockAllocResult::Allocate(buffers[1].as_mut_ptr()),
+                                                       MockAllocResult::Deallocate,
+                                                       MockAllocResult::Deallocate]),
+                                   arena_options);
+        let first = vec![arena.allocate(40, PowerOfTwo::new(1)), arena.allocate(40, PowerOfTwo::new(1))];
+        arena.reset();
+        let second = vec![arena.allocate(40, PowerOfTwo::new(1)), arena.allocate(40, PowerOfTwo::new(1))];
+        assert!(first.contains(&second[0]));
+        assert!(first.contains(&second[1]));
+        arena.reset();
+        arena.trim(64);
+        arena.trim(0);
+    }
+}
+#[test]
+fn arena_tail_test() {
+    unsafe {
+        let arena_options = ArenaOptions {
+            start_block_size: 64,
+            recommended_max_block_size: 64,
+            ..ArenaOptions::default()
+        };
+        let mut arena = Arena::new(CheckedAlloc::new(HeapAlloc, Default::default()), arena_options);
+        let first = arena.allocate(40, PowerOfTwo::new(1));
+        arena.allocate(50, PowerOfTwo::new(1));
+        assert_eq!(first.offset(40), arena.allocate(20, PowerOfTwo::new(1)));
+        assert_eq!(20, arena.recovered_bytes());
+        arena.reset();
+        arena.allocate(60, PowerOfTwo::new(1));
+        arena.allocate(10, PowerOfTwo::new(1));
+        assert_eq!(20, arena.recovered_bytes());
+    }
+}
+#[test]
+fn arena_large_test() {
+    unsafe {
+        let arena_options = ArenaOptions {
+            start_block_size: 64,
+            recommended_max_block_size: 64,
+            large_object_threshold: 32,
+            ..ArenaOptions::default()
+        };
+        let mut arena = Arena::new(CheckedAlloc::new(HeapAlloc, Default::default()), arena_options);
+        let first = arena.allocate(8, PowerOfTwo::new(1));
+        let large = arena.allocate(100, PowerOfTwo::new(16));
+        assert!(PowerOfTwo::new(16).is_aligned_ptr_mut(large));
+        assert_eq!(first.offset(8), arena.allocate(8, PowerOfTwo::new(1)));
+        arena.deallocate(large, 100, PowerOfTwo::new(16));
+        let mark = arena.checkpoint();
+        arena.allocate(1000, PowerOfTwo::new(1));
+        arena.rollback(mark);
+        let kept = arena.allocate(200, PowerOfTwo::new(1));
+        let mark = arena.checkpoint();
+        arena.deallocate(kept, 200, PowerOfTwo::new(1));
+        let newer = arena.allocate(300, PowerOfTwo::new(1));
+        arena.rollback(mark);
+        assert_eq!(16, arena.stats().allocated_bytes);
+        assert_eq!(0, arena.stats().padding_bytes);
+        assert!(arena.blocks().all(|block| block.begin != newer));
+        let large = arena.allocate(100, PowerOfTwo::new(1));
+        arena.deallocate(large, 100, PowerOfTwo::new(1));
+        assert_eq!(16, arena.stats().allocated_bytes);
+    }
+}
+#[test]
+fn arena_stats_test() {
+    unsafe {
+        let arena_options = ArenaOptions {
+            start_block_size: 64,
+            recommended_max_block_size: 64,
+            large_object_threshold: 32,
+            ..ArenaOptions::default()
+        };
+        let mut arena = Arena::new(CheckedAlloc::new(HeapAlloc, Default::default()), arena_options);
+        let first = arena.allocate(10, PowerOfTwo::new(1));
+        arena.allocate(4, PowerOfTwo::new(8));
+        arena.allocate(30, PowerOfTwo::new(1));
+        arena.allocate(30, PowerOfTwo::new(1));
+        arena.allocate(100, PowerOfTwo::new(1));
+        let stats = arena.stats();
+        assert_eq!(64 + 64 + 100, stats.reserved_bytes);
+        assert_eq!(174, stats.allocated_bytes);
+        assert_eq!(util::distance(first.offset(10), PowerOfTwo::new(8).align_ptr_mut(first.offset(10))),
+                   stats.padding_bytes);
+        assert_eq!(64 - 44 - stats.padding_bytes, stats.wasted_bytes);
+        assert_eq!(3, stats.block_count);
+        assert_eq!(100, stats.largest_block);
+        let blocks: Vec<ArenaBlock> = arena.blocks().collect();
+        assert_eq!(first, blocks[0].begin);
+        assert_eq!(first.offset(44 + stats.padding_bytes as isize), blocks[0].next);
+        assert_eq!(blocks[1].begin.offset(30), blocks[1].next);
+        assert_eq!(blocks[2].end, blocks[2].next);
+        arena.reset();
+        let stats = arena.stats();
+        assert_eq!(0, stats.allocated_bytes);
+        assert_eq!(2, stats.block_count);
+        let large = arena.allocate(100, PowerOfTwo::new(64));
+        let block = arena.blocks().last().unwrap();
+        assert_eq!(util::distance(block.begin, large), arena.stats().padding_bytes);
+        assert_eq!(large.offset(100), block.next);
+    }
+}
+#[test]
+fn arena_retired_blocks_test() {
+    unsafe {
+        let arena_options = ArenaOptions {
+            start_block_size: 16,
+            recommended_max_block_size: 16,
+            ..ArenaOptions::default()
+        };
+        let mut arena = Arena::new(CheckedAlloc::new(HeapAlloc, Default::default()), arena_options);
+        // Retires far more blocks than there are tail slots.
+        for _ in 0..4 * MAX_TAILS {
+            arena.allocate(10, PowerOfTwo::new(1));
+        }
+        let handed_out = arena.blocks().fold(0, |total, block| total + util::distance(block.begin, block.next));
+        assert_eq!(arena.stats().allocated_bytes, handed_out);
+    }
+}
+#[test]
+fn arena_growth_test() {
+    assert_eq!(8192, GeometricGrowth(2).next_block_size(4096));
+    assert_eq!(5120, LinearGrowth(1024).next_block_size(4096));
+    assert_eq!(6000, CappedGrowth(GeometricGrowth(2), 6000).next_block_size(4096));
+    assert_eq!(usize::max_value(), GeometricGrowth(2).next_block_size(usize::max_value()));
+    unsafe {
+        let arena_options = ArenaOptions {
+            start_block_size: 64,
+            recommended_max_block_size: 64,
+            growth: Some(Box::new(LinearGrowth(64))),
+            ..ArenaOptions::default()
+        };
+        let mut arena = Arena::new(CheckedAlloc::new(HeapAlloc, Default::default()), arena_options);
+        for _ in 0..3 {
+            arena.allocate(60, PowerOfTwo::new(1));
+        }
+        // The policy, not recommended_max_block_size, decides how far blocks grow.
+        assert!(arena.blocks().any(|block| util::distance(block.begin, block.end) >= 128));
+    }
+}
+#[test]
+fn arena_limit_test() {
+    unsafe {
+        let exhausted = Arc::new(AtomicUsize::new(0));
+        let on_exhausted = exhausted.clone();
+        let arena_options = ArenaOptions {
+            start_block_size: 64,
+            recommended_max_block_size: 64,
+            large_object_threshold: 64,
+            max_total_bytes: 200,
+            on_exhausted: Some(Box::new(move |size| on_exhausted.store(size, Ordering::SeqCst))),
+            ..ArenaOptions::default()
+        };
+        let mut arena = Arena::new(CheckedAlloc::new(HeapAlloc, Default::default()), arena_options);
+        for _ in 0..3 {
+            assert!(!arena.allocate(60, PowerOfTwo::new(1)).is_null());
+        }
+        assert!(arena.allocate(100, PowerOfTwo::new(1)).is_null());
+        assert_eq!(100, exhausted.load(Ordering::SeqCst));
+        assert!(arena.allocate(60, PowerOfTwo::new(1)).is_null());
+        assert_eq!(60, exhausted.load(Ordering::SeqCst));
+        arena.reset();
+        arena.trim(0);
+        assert!(!arena.allocate(100, PowerOfTwo::new(1)).is_null());
+    }
+}
+#[test]
+fn arena_overflow_test() {
+    unsafe {
+        let huge = usize::max_value() - 3;
+        let mut arena: Arena<CheckedAlloc<HeapAlloc>> = Default::default();
+        assert!(arena.allocate(huge, PowerOfTwo::new(8)).is_null());
+        let small = arena.allocate(8, PowerOfTwo::new(8));
+        assert!(!small.is_null());
+        assert!(arena.allocate(huge, PowerOfTwo::new(8)).is_null());
+        assert_eq!(8, arena.reallocate_inplace(small, 8, huge, PowerOfTwo::new(8)));
+        let arena_options = ArenaOptions { large_object_threshold: usize::max_value(), ..ArenaOptions::default() };
+        let mut arena = Arena::new(CheckedAlloc::new(HeapAlloc, Default::default()), arena_options);
+        assert!(arena.allocate(huge, PowerOfTwo::new(8)).is_null());
+    }
+}
+#[test]
+fn arena_typed_test() {
+    let arena_options = ArenaOptions {
+        start_block_size: 16,
+        recommended_max_block_size: 64,
+        ..ArenaOptions::default()
+    };
+    let arena = Arena::new(CheckedAlloc::new(HeapAlloc, Default::default()), arena_options);
+    let x = arena.alloc(1u64);
+    let unit = arena.alloc(());
+    let numbers = arena.alloc_slice_copy(&[1u32, 2, 3]);
+    let empty = arena.alloc_slice_copy::<u32>(&[]);
+    let name = arena.alloc_str("hello");
+    let squares = arena.alloc_from_iter((0..10u16).map(|i| i * i));
+    *x += 1;
+    numbers[0] = 10;
+    assert_eq!(2, *x);
+    assert_eq!((), *unit);
+    assert_eq!(vec![10, 2, 3], numbers.to_vec());
+    assert_eq!(0, empty.len());
+    assert_eq!("hello", &*name);
+    assert_eq!(81, squares[9]);
+    assert!(PowerOfTwo::align_of::<u64>().is_aligned_ptr_mut(x as *mut u64));
+    let limited = Arena::new(CheckedAlloc::new(HeapAlloc, Default::default()),
+                             ArenaOptions {
+                                 start_block_size: 64,
+                                 recommended_max_block_size: 64,
+                                 max_total_bytes: 64,
+                                 ..ArenaOptions::default()
+                             });
+    assert_eq!(Some(&mut 1u8), limited.try_alloc(1u8));
+    assert!(limited.try_alloc_slice_copy(&[0u8; 100]).is_none());
+    assert!(limited.try_alloc_str(::std::str::from_utf8(&[b'x'; 100]).unwrap()).is_none());
+    assert!(limited.try_alloc_from_iter(0..100u8).is_none());
+    assert_eq!(Some(&mut [][..]), limited.try_alloc_slice_copy::<u8>(&[]));
+}
+#[test]
+fn arena_child_test() {
+    unsafe {
+        let mut parent = Arena::new(CheckedAlloc::new(HeapAlloc, Default::default()), Default::default());
+        let top = parent.allocate(8, PowerOfTwo::new(1));
+        parent.deallocate(top, 8, PowerOfTwo::new(1));
+        {
+            let mut child = parent.child();
+            *child.alloc(5u32) += 1;
+            {
+                let grandchild = child.child();
+                grandchild.alloc_slice_copy(&[0u8; 10000]);
+            }
+            for i in 0..1000 {
+                child.alloc(i);
+            }
+        }
+        assert_eq!(top, parent.allocate(8, PowerOfTwo::new(1)));
+    }
+}
 #[cfg(benchmark)]
 const BENCH_COUNT: usize = 1024 * 256;
 #[cfg(benchmark)]
@@ -0,0 +1,188 @@
+use std::ops;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use allocator::Allocator;
+use arena_alloc::Arena;
+use arena_alloc::ArenaOptions;
+#[cfg(test)]
+use allocator::OwnedAllocator;
+#[cfg(test)]
+use heap_alloc::HeapAlloc;
+#[cfg(test)]
+use std::sync::Arc;
+#[cfg(test)]
+use std::thread;
+#[cfg(test)]
+use util::PowerOfTwo;
+pub struct ArenaPoolOptions {
+    pub max_idle_arenas: usize,
+    pub max_idle_bytes: usize,
+}
+impl Default for ArenaPoolOptions {
+    fn default() -> Self {
+        return ArenaPoolOptions {
+            max_idle_arenas: 16,
+            max_idle_bytes: 16 * 65536,
+        };
+    }
+}
+struct IdleArenas<A: Allocator> {
+    arenas: Vec<Arena<A>>,
+    bytes: usize,
+}
+pub struct ArenaPool<A: Allocator + Send> {
+    allocator: A,
+    arena_options: fn() -> ArenaOptions,
+    options: ArenaPoolOptions,
+    idle: Mutex<IdleArenas<A>>,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+impl<A: Allocator + Send> ArenaPool<A> {
+    pub fn new(allocator: A, arena_options: fn() -> ArenaOptions, options: ArenaPoolOptions) -> Self {
+        return ArenaPool {
+            allocator: allocator,
+            arena_options: arena_options,
+            options: options,
+            idle: Mutex::new(IdleArenas {
+                arenas: Vec::new(),
+                bytes: 0,
+            }),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        };
+    }
+    pub fn get(&self) -> PooledArena<A> {
+        let arena = self.idle.lock().unwrap().take();
+        let arena = match arena {
+            Some(arena) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                arena
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                Arena::new(self.allocator, (self.arena_options)())
+            }
+        };
+        return PooledArena {
+            pool: self,
+            arena: Some(arena),
+        };
+    }
+    // Trimming and dropping free blocks to the backing allocator, so neither happens under the lock: arena outlives
+    // every guard taken here.
+    fn release(&self, mut arena: Arena<A>) {
+        arena.reset();
+        let room = {
+            let idle = self.idle.lock().unwrap();
+            if idle.arenas.len() >= self.options.max_idle_arenas {
+                return;
+            }
+            self.options.max_idle_bytes.saturating_sub(idle.bytes)
+        };
+        arena.trim(room);
+        let mut idle = self.idle.lock().unwrap();
+        // Other arenas may have been released in the meantime.
+        if idle.arenas.len() >= self.options.max_idle_arenas ||
+           idle.bytes.saturating_add(arena.reserved_bytes()) > self.options.max_idle_bytes {
+            return;
+        }
+        idle.bytes += arena.reserved_bytes();
+        idle.arenas.push(arena);
+    }
+    pub fn hits(&self) -> usize {
+        return self.hits.load(Ordering::Relaxed);
+    }
+    pub fn misses(&self) -> usize {
+        return self.misses.load(Ordering::Relaxed);
+    }
+    pub fn idle_count(&self) -> usize {
+        return self.idle.lock().unwrap().arenas.len();
+    }
+    pub fn idle_bytes(&self) -> usize {
+        return self.idle.lock().unwrap().bytes;
+    }
+}
+impl<A: Allocator> IdleArenas<A> {
+    fn take(&mut self) -> Option<Arena<A>> {
+        let arena = self.arenas.pop();
+        if let Some(ref arena) = arena {
+            self.bytes -= arena.reserved_bytes();
+        }
+        return arena;
+    }
+}
+pub struct PooledArena<'a, A: Allocator + Send + 'a> {
+    pool: &'a ArenaPool<A>,
+    arena: Option<Arena<A>>,
+}
+impl<'a, A: Allocator + Send> ops::Deref for PooledArena<'a, A> {
+    type Target = Arena<A>;
+    fn deref(&self) -> &Arena<A> {
+        return self.arena.as_ref().unwrap();
+    }
+}
+impl<'a, A: Allocator + Send> ops::DerefMut for PooledArena<'a, A> {
+    fn deref_mut(&mut self) -> &mut Arena<A> {
+        return self.arena.as_mut().unwrap();
+    }
+}
+impl<'a, A: Allocator + Send> Drop for PooledArena<'a, A> {
+    fn drop(&mut self) {
+        if let Some(arena) = self.arena.take() {
+            self.pool.release(arena);
+        }
+    }
+}
+#[cfg(test)]
+fn small_arenas() -> ArenaOptions {
+    return ArenaOptions {
+        start_block_size: 64,
+        recommended_max_block_size: 64,
+        ..ArenaOptions::default()
+    };
+}
+#[test]
+fn arena_pool_test() {
+    unsafe {
+        let pool = ArenaPool::new(HeapAlloc,
+                                  small_arenas,
+                                  ArenaPoolOptions {
+                                      max_idle_arenas: 1,
+                                      max_idle_bytes: 64,
+                                  });
+        {
+            let mut first = pool.get();
+            let mut second = pool.get();
+            first.allocate(60, PowerOfTwo::new(1));
+            first.allocate(60, PowerOfTwo::new(1));
+            second.allocate(10, PowerOfTwo::new(1));
+        }
+        assert_eq!(2, pool.misses());
+        assert_eq!(1, pool.idle_count());
+        assert_eq!(64, pool.idle_bytes());
+        pool.get().allocate(10, PowerOfTwo::new(1));
+        assert_eq!(1, pool.hits());
+    }
+}
+#[test]
+fn arena_pool_thread_test() {
+    let pool = Arc::new(ArenaPool::new(HeapAlloc, ArenaOptions::default, Default::default()));
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            let pool = pool.clone();
+            thread::spawn(move || {
+                for _ in 0..100 {
+                    let arena = pool.get();
+                    arena.alloc_slice_copy(&[1u8; 100]);
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(400, pool.hits() + pool.misses());
+    assert!(pool.idle_count() <= 4);
+}
@@ -7,49 +7,620 @@ use interval_map::Interval;
 use std::fmt;
 use allocator::ShareableAllocator;
 use util::PowerOfTwo;
+use stack_trace::StackTable;
+use stack_trace::StackTrace;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::io;
+use std::io::Write;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::fs::File;
+use std::ops::Deref;
+use std::ops::DerefMut;
+use std::path::Path;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
 #[cfg(test)]
 use allocator::SharedAlloc;
 #[cfg(test)]
 use heap_alloc::HeapAlloc;
+use std::ptr;
 #[cfg(test)]
 use std::ptr::null_mut;
 #[cfg(test)]
 use simple_alloc::MockAllocResult;
 #[cfg(test)]
 use simple_alloc::MockAlloc;
+#[cfg(test)]
+use std::cell::RefCell;
+#[cfg(test)]
+use std::rc::Rc;
+#[cfg(test)]
+use std::env;
+#[cfg(test)]
+use std::fs;
 #[derive(Clone,Copy,Eq,Ord,PartialEq,PartialOrd)]
 struct Allocation {
     ptr: *mut u8,
     requested_size: usize,
     align: PowerOfTwo,
+    // Counts allocations made by this CheckedAlloc, so a rerun of a deterministic test sees the same numbers.
+    sequence: usize,
+    // Index into the allocator's StackTable, when stacks are captured.
+    stack: Option<usize>,
+    // Made inside ignore_leaks_in, or reallocated from a block that was.
+    suppressed: bool,
+}
+#[derive(Clone,Copy,Eq,PartialEq)]
+struct FreedAllocation {
+    allocation: Allocation,
+    // The allocation sequence number current when the block was freed.
+    freed_sequence: usize,
+    stack: Option<usize>,
+}
+impl Allocation {
+    fn info(&self, offset: usize, freed_sequence: Option<usize>) -> AllocationInfo {
+        return AllocationInfo {
+            base: self.ptr,
+            requested_size: self.requested_size,
+            align: self.align,
+            sequence: self.sequence,
+            offset: offset,
+            freed_sequence: freed_sequence,
+        };
+    }
 }
 impl Debug for Allocation {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "Allocation {{ align : {} }}", self.align)
     }
 }
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum CheckedAllocErrorKind {
+    Leak,
+    Misaligned,
+    Overlap,
+    NotLive,
+    WrongAlignment,
+    WrongSize,
+    RedZone,
+    DoubleFree,
+    InteriorPointer,
+    WrongInstance,
+    WriteAfterFree,
+    // A zero size was passed where the allocator interface requires a positive one.
+    ZeroSize,
+    // The inner allocator returned a size that breaks the allocator interface.
+    BadBackend,
+}
+#[derive(Clone,Debug)]
+pub struct CheckedAllocError {
+    pub kind: CheckedAllocErrorKind,
+    pub message: String,
+}
+pub enum ErrorPolicy {
+    Panic,
+    // Writes the report to stderr and carries on; the offending call is ignored.
+    Log,
+    Callback(Box<Fn(&CheckedAllocError)>),
+}
+impl ErrorPolicy {
+    pub fn report(&self, kind: CheckedAllocErrorKind, message: String) {
+        match *self {
+            ErrorPolicy::Panic => panic!("{}", message),
+            ErrorPolicy::Log => {
+                let _ = writeln!(io::stderr(), "{}", message);
+            }
+            ErrorPolicy::Callback(ref callback) => {
+                callback(&CheckedAllocError {
+                    kind: kind,
+                    message: message,
+                })
+            }
+        }
+    }
+}
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub struct LeakRecord {
+    pub ptr: *mut u8,
+    pub requested_size: usize,
+    pub align: PowerOfTwo,
+    pub sequence: usize,
+}
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub struct AllocationInfo {
+    pub base: *mut u8,
+    pub requested_size: usize,
+    pub align: PowerOfTwo,
+    pub sequence: usize,
+    pub offset: usize,
+    // Set when the address is in a recently freed block rather than a live one.
+    pub freed_sequence: Option<usize>,
+}
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum Watch {
+    Sequence(usize),
+    Pointer(*mut u8),
+    Size(usize),
+}
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum WatchEvent {
+    Allocate,
+    Reallocate,
+    Free,
+}
+pub enum WatchAction {
+    Panic,
+    Callback(Box<Fn(WatchEvent, &AllocationInfo)>),
+}
+impl Watch {
+    fn matches(&self, allocation: &Allocation) -> bool {
+        match *self {
+            Watch::Sequence(sequence) => return allocation.sequence == sequence,
+            Watch::Pointer(ptr) => return allocation.ptr == ptr,
+            Watch::Size(size) => return allocation.requested_size == size,
+        }
+    }
+}
+// Leaks matching any of these rules are left out of the report made on drop.
+#[derive(Clone,Debug,Eq,PartialEq)]
+pub enum LeakSuppression {
+    Size(usize),
+    Align(PowerOfTwo),
+    // Matches the resolved allocation stack, so it only applies when stacks are captured.
+    StackContains(String),
+}
+impl LeakSuppression {
+    // Reads one rule per line: "size 24", "align 16" or "stack some::function". Blank lines and lines starting
+    // with # are skipped.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Vec<LeakSuppression>> {
+        let mut rules = Vec::new();
+        for line in BufReader::new(try!(File::open(path))).lines() {
+            let line = try!(line);
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut words = line.splitn(2, ' ');
+            let key = words.next().unwrap();
+            let value = words.next().unwrap_or("").trim();
+            let rule = match key {
+                "size" => value.parse().ok().map(LeakSuppression::Size),
+                "align" => {
+                    value.parse::<usize>()
+                        .ok()
+                        .and_then(|align| if align.is_power_of_two() {
+                            Some(LeakSuppression::Align(PowerOfTwo::new(align)))
+                        } else {
+                            None
+                        })
+                }
+                "stack" if !value.is_empty() => Some(LeakSuppression::StackContains(value.to_string())),
+                _ => None,
+            };
+            match rule {
+                Some(rule) => rules.push(rule),
+                None => {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                              format!("bad leak suppression: {}", line)))
+                }
+            }
+        }
+        return Ok(rules);
+    }
+}
+// The live blocks of a CheckedAlloc at one point in time.
+pub struct HeapSnapshot {
+    live: Vec<LeakRecord>,
+    next_sequence: usize,
+}
+// A reallocated block keeps its sequence number, so it shows up in neither list.
+#[derive(Debug)]
+pub struct HeapDiff {
+    pub created: Vec<LeakRecord>,
+    pub freed: Vec<LeakRecord>,
+    // Includes blocks that were created and freed again between the two snapshots.
+    pub allocation_count: usize,
+}
+impl HeapSnapshot {
+    pub fn live(&self) -> &[LeakRecord] {
+        return &self.live;
+    }
+    pub fn diff(&self, later: &HeapSnapshot) -> HeapDiff {
+        let mut created = Vec::new();
+        let mut freed = Vec::new();
+        let (mut i, mut j) = (0, 0);
+        // Both lists are sorted by sequence.
+        while i < self.live.len() || j < later.live.len() {
+            if j == later.live.len() || (i < self.live.len() && self.live[i].sequence < later.live[j].sequence) {
+                freed.push(self.live[i]);
+                i += 1;
+            } else if i == self.live.len() || later.live[j].sequence < self.live[i].sequence {
+                created.push(later.live[j]);
+                j += 1;
+            } else {
+                i += 1;
+                j += 1;
+            }
+        }
+        return HeapDiff {
+            created: created,
+            freed: freed,
+            allocation_count: later.next_sequence - self.next_sequence,
+        };
+    }
+}
+impl HeapDiff {
+    pub fn created_bytes(&self) -> usize {
+        return self.created.iter().fold(0, |total, record| total + record.requested_size);
+    }
+    pub fn freed_bytes(&self) -> usize {
+        return self.freed.iter().fold(0, |total, record| total + record.requested_size);
+    }
+}
 #[derive(Clone,Copy,Eq,Ord,PartialEq,PartialOrd)]
 pub struct CheckedAllocOptions {
     pub ignore_leaks: bool,
+    // Record the stack of every allocation so that reports can say where a block came from.
+    pub capture_stacks: bool,
+    // Guard bytes placed before and after every block, checked when the block is freed. Zero disables them.
+    pub red_zone: usize,
+    pub red_zone_pattern: u8,
+    // How many freed blocks to remember for double free reports.
+    pub free_history: usize,
+    // Record live blocks in a registry shared by all CheckedAllocs, so that a free through the wrong instance can
+    // name the owner. Enable it on at most one layer of a stack: the registry holds one owner per address.
+    pub tag_instances: bool,
 }
 impl Default for CheckedAllocOptions {
     fn default() -> Self {
-        return CheckedAllocOptions { ignore_leaks: false };
+        return CheckedAllocOptions {
+            ignore_leaks: false,
+            capture_stacks: false,
+            red_zone: 0,
+            red_zone_pattern: 0xFD,
+            free_history: 1024,
+            tag_instances: false,
+        };
+    }
+}
+static NEXT_INSTANCE: AtomicUsize = AtomicUsize::new(0);
+static REGISTRY_LOCK: AtomicBool = AtomicBool::new(false);
+// Maps blocks of tagging CheckedAllocs to the instance that owns them. Created on first use, guarded by
+// REGISTRY_LOCK.
+static mut REGISTRY: *mut TreeIntervalMap<usize, usize> = 0 as *mut TreeIntervalMap<usize, usize>;
+// Releases REGISTRY_LOCK even if the registry update panics.
+struct RegistryGuard;
+impl Drop for RegistryGuard {
+    fn drop(&mut self) {
+        REGISTRY_LOCK.store(false, Ordering::Release);
+    }
+}
+fn with_registry<R, F>(f: F) -> R
+    where F: FnOnce(&mut TreeIntervalMap<usize, usize>) -> R
+{
+    while REGISTRY_LOCK.compare_and_swap(false, true, Ordering::Acquire) {}
+    let _guard = RegistryGuard;
+    unsafe {
+        if REGISTRY.is_null() {
+            REGISTRY = Box::into_raw(Box::new(TreeIntervalMap::new()));
+        }
+        return f(&mut *REGISTRY);
+    }
+}
+// Ends an ignore_leaks_in scope even if its closure panics. The closure borrows the allocator through the guard.
+struct IgnoreLeaksGuard<'a, A: OwnedAllocator + 'a>(&'a mut CheckedAlloc<A>);
+impl<'a, A: OwnedAllocator> Deref for IgnoreLeaksGuard<'a, A> {
+    type Target = CheckedAlloc<A>;
+    fn deref(&self) -> &CheckedAlloc<A> {
+        return &*self.0;
+    }
+}
+impl<'a, A: OwnedAllocator> DerefMut for IgnoreLeaksGuard<'a, A> {
+    fn deref_mut(&mut self) -> &mut CheckedAlloc<A> {
+        return &mut *self.0;
+    }
+}
+impl<'a, A: OwnedAllocator> Drop for IgnoreLeaksGuard<'a, A> {
+    fn drop(&mut self) {
+        self.0.ignoring -= 1;
     }
 }
 pub struct CheckedAlloc<A: OwnedAllocator> {
     internal: A,
+    instance: usize,
     allocated: TreeIntervalMap<usize, Allocation>,
     options: CheckedAllocOptions,
+    policy: ErrorPolicy,
+    stacks: StackTable,
+    next_sequence: usize,
+    // Recently freed blocks, cleared where their addresses are reused; history holds them oldest first.
+    freed: TreeIntervalMap<usize, FreedAllocation>,
+    history: VecDeque<(Interval<usize>, usize)>,
+    watches: Vec<(Watch, WatchAction)>,
+    // Depth of nested ignore_leaks_in calls.
+    ignoring: usize,
+    suppressions: Vec<LeakSuppression>,
 }
 impl<A: OwnedAllocator> CheckedAlloc<A> {
     pub fn new(alloc: A, options: CheckedAllocOptions) -> Self {
+        return Self::with_policy(alloc, options, ErrorPolicy::Panic);
+    }
+    // Like new, but problems are handled by policy instead of panicking.
+    pub fn with_policy(alloc: A, options: CheckedAllocOptions, policy: ErrorPolicy) -> Self {
         return CheckedAlloc {
             internal: alloc,
+            instance: NEXT_INSTANCE.fetch_add(1, Ordering::Relaxed),
             allocated: TreeIntervalMap::new(),
             options: options,
+            policy: policy,
+            stacks: StackTable::new(),
+            next_sequence: 0,
+            freed: TreeIntervalMap::new(),
+            history: VecDeque::new(),
+            watches: Vec::new(),
+            ignoring: 0,
+            suppressions: Vec::new(),
+        };
+    }
+    // Leaves leaks matching rule out of the report made on drop.
+    pub fn suppress(&mut self, rule: LeakSuppression) {
+        self.suppressions.push(rule);
+    }
+    // Blocks allocated while f runs are never reported as leaks.
+    pub fn ignore_leaks_in<R, F>(&mut self, f: F) -> R
+        where F: FnOnce(&mut Self) -> R
+    {
+        self.ignoring += 1;
+        let mut guard = IgnoreLeaksGuard(self);
+        return f(&mut *guard);
+    }
+    fn suppressed(&self, allocation: &Allocation) -> bool {
+        if allocation.suppressed {
+            return true;
+        }
+        let mut stack = None;
+        for rule in &self.suppressions {
+            let matches = match *rule {
+                LeakSuppression::Size(size) => allocation.requested_size == size,
+                LeakSuppression::Align(align) => allocation.align == align,
+                LeakSuppression::StackContains(ref pattern) => {
+                    match allocation.stack {
+                        Some(id) => {
+                            if stack.is_none() {
+                                stack = Some(format!("{}", self.stacks.get(id)));
+                            }
+                            stack.as_ref().unwrap().contains(&pattern[..])
+                        }
+                        None => false,
+                    }
+                }
+            };
+            if matches {
+                return true;
+            }
+        }
+        return false;
+    }
+    pub fn instance(&self) -> usize {
+        return self.instance;
+    }
+    fn tag(&self, int: Interval<usize>, owner: Option<usize>) {
+        if self.options.tag_instances {
+            with_registry(|registry| registry.fill(int, owner));
+        }
+    }
+    // Fires action whenever a block matching watch is created, reallocated or freed. A reallocation matches on
+    // either the old block or the new one.
+    pub fn watch(&mut self, watch: Watch, action: WatchAction) {
+        self.watches.push((watch, action));
+    }
+    pub fn clear_watches(&mut self) {
+        self.watches.clear();
+    }
+    fn fire_watches(&self, event: WatchEvent, old: Option<&Allocation>, new: &Allocation) {
+        for &(ref watch, ref action) in &self.watches {
+            if !watch.matches(new) && !old.map_or(false, |old| watch.matches(old)) {
+                continue;
+            }
+            match *action {
+                WatchAction::Panic => {
+                    panic!("CheckedAlloc: watchpoint {:?} hit by {:?} of block #{} at {:X} ({} bytes, align {}){}",
+                           watch,
+                           event,
+                           new.sequence,
+                           new.ptr as usize,
+                           new.requested_size,
+                           new.align,
+                           self.allocated_at(new.stack))
+                }
+                WatchAction::Callback(ref callback) => callback(event, &new.info(0, None)),
+            }
+        }
+    }
+    fn capture_stack(&mut self) -> Option<usize> {
+        if self.options.capture_stacks {
+            return Some(self.stacks.intern(StackTrace::capture()));
+        } else {
+            return None;
+        }
+    }
+    fn remember_free(&mut self, int: Interval<usize>, allocation: Allocation) {
+        if self.options.free_history == 0 {
+            return;
+        }
+        let stack = self.capture_stack();
+        self.freed.fill(int,
+                        Some(FreedAllocation {
+                            allocation: allocation,
+                            freed_sequence: self.next_sequence,
+                            stack: stack,
+                        }));
+        self.history.push_back((int, allocation.sequence));
+        while self.history.len() > self.options.free_history {
+            let (int, sequence) = self.history.pop_front().unwrap();
+            self.forget_free(int, sequence);
+        }
+    }
+    // Clears what is left of one freed block; parts of it may since have been reused or freed again.
+    fn forget_free(&mut self, int: Interval<usize>, sequence: usize) {
+        let pieces: Vec<Interval<usize>> = self.freed
+            .iter(int.front())
+            .take_while(|&(piece, _)| piece.front() <= int.back())
+            .filter(|&(_, freed)| freed.map_or(false, |freed| freed.allocation.sequence == sequence))
+            .map(|(piece, _)| piece)
+            .collect();
+        for piece in pieces {
+            self.freed.fill(piece, None);
+        }
+    }
+    fn freed_at(&self, stack: Option<usize>) -> String {
+        match stack {
+            Some(stack) => return format!("\nfreed at:\n{}", self.stacks.get(stack)),
+            None => return String::new(),
+        }
+    }
+    fn report(&self, kind: CheckedAllocErrorKind, message: String) {
+        self.policy.report(kind, message);
+    }
+    // Reports a zero size passed to call; the caller ignores the call when this returns false.
+    fn check_size(&self, size: usize, call: &str) -> bool {
+        if size == 0 {
+            self.report(CheckedAllocErrorKind::ZeroSize,
+                        format!("CheckedAlloc: {} called with size 0", call));
+            return false;
+        }
+        return true;
+    }
+    // Every block that is still live, oldest first.
+    pub fn leaks(&self) -> Vec<LeakRecord> {
+        let mut leaks = Vec::new();
+        for (_, allocation) in self.allocated.iter(0) {
+            if let Some(allocation) = allocation {
+                leaks.push(LeakRecord {
+                    ptr: allocation.ptr,
+                    requested_size: allocation.requested_size,
+                    align: allocation.align,
+                    sequence: allocation.sequence,
+                });
+            }
+        }
+        leaks.sort_by_key(|leak| leak.sequence);
+        return leaks;
+    }
+    // Finds the live or recently freed block containing ptr.
+    pub fn describe(&self, ptr: *const u8) -> Option<AllocationInfo> {
+        let (allocation, freed_sequence) = match self.allocated.get(ptr as usize) {
+            Some(allocation) => (allocation, None),
+            None => {
+                match self.freed.get(ptr as usize) {
+                    Some(freed) => (freed.allocation, Some(freed.freed_sequence)),
+                    None => return None,
+                }
+            }
+        };
+        return Some(allocation.info(ptr as usize - allocation.ptr as usize, freed_sequence));
+    }
+    pub fn snapshot(&self) -> HeapSnapshot {
+        return HeapSnapshot {
+            live: self.leaks(),
+            next_sequence: self.next_sequence,
         };
     }
+    // Bytes between the start of the inner block and the pointer handed out; keeps that pointer aligned.
+    fn front_pad(&self, align: PowerOfTwo) -> usize {
+        if self.options.red_zone == 0 {
+            return 0;
+        } else {
+            return align.align_size(self.options.red_zone);
+        }
+    }
+    fn outer_size(&self, size: usize, align: PowerOfTwo) -> usize {
+        return self.front_pad(align) + size + self.options.red_zone;
+    }
+    unsafe fn fill_guards(&self, ptr: *mut u8, size: usize, align: PowerOfTwo) {
+        let front = self.front_pad(align);
+        ptr::write_bytes(ptr.offset(-(front as isize)), self.options.red_zone_pattern, front);
+        ptr::write_bytes(ptr.offset(size as isize), self.options.red_zone_pattern, self.options.red_zone);
+    }
+    unsafe fn verify_guards(&self, allocation: &Allocation) {
+        let front = self.front_pad(allocation.align) as isize;
+        let back = (allocation.requested_size + self.options.red_zone) as isize;
+        let mut changed = Vec::new();
+        for offset in (-front..0).chain(allocation.requested_size as isize..back) {
+            let byte = *allocation.ptr.offset(offset);
+            if byte != self.options.red_zone_pattern {
+                changed.push(format!("{}: {:02X}", offset, byte));
+            }
+        }
+        if !changed.is_empty() {
+            self.report(CheckedAllocErrorKind::RedZone,
+                        format!("CheckedAlloc: red zone of block {:X} ({} bytes) corrupted at offsets [{}], expected \
+                                 {:02X}{}",
+                                allocation.ptr as usize,
+                                allocation.requested_size,
+                                changed.join(", "),
+                                self.options.red_zone_pattern,
+                                self.allocated_at(allocation.stack)));
+        }
+    }
+    // Checks the red zones of every live block.
+    pub fn verify_all(&self) {
+        if self.options.red_zone == 0 {
+            return;
+        }
+        for (_, allocation) in self.allocated.iter(0) {
+            if let Some(allocation) = allocation {
+                unsafe {
+                    self.verify_guards(&allocation);
+                }
+            }
+        }
+    }
+    unsafe fn block_size(&self, requested_size: usize, align: PowerOfTwo) -> usize {
+        if self.options.red_zone == 0 {
+            return self.internal.usable_size(requested_size, align);
+        } else {
+            return requested_size;
+        }
+    }
+    fn allocated_at(&self, stack: Option<usize>) -> String {
+        match stack {
+            Some(stack) => return format!("\nallocated at:\n{}", self.stacks.get(stack)),
+            None => return String::new(),
+        }
+    }
+    fn leak_report(&self) -> Option<String> {
+        let mut count = 0;
+        let mut bytes = 0;
+        let mut groups: HashMap<Option<usize>, (usize, usize)> = HashMap::new();
+        for (int, allocation) in self.allocated.iter(0) {
+            match allocation {
+                Some(allocation) if !self.suppressed(&allocation) => {
+                    count += 1;
+                    bytes += int.len().unwrap();
+                    let group = groups.entry(allocation.stack).or_insert((0, 0));
+                    group.0 += 1;
+                    group.1 += int.len().unwrap();
+                }
+                _ => {}
+            }
+        }
+        if count == 0 {
+            return None;
+        }
+        let mut report = format!("LeakChecker: {} bytes in {} blocks leaked.", bytes, count);
+        let mut groups: Vec<(Option<usize>, (usize, usize))> = groups.into_iter().collect();
+        groups.sort_by(|a, b| (b.1).1.cmp(&(a.1).1));
+        for (stack, (count, bytes)) in groups {
+            if stack.is_some() {
+                report.push_str(&format!("\n{} bytes in {} blocks{}", bytes, count, self.allocated_at(stack)));
+            }
+        }
+        return Some(report);
+    }
 }
 impl<A: OwnedAllocator + Default> Default for CheckedAlloc<A> {
     fn default() -> Self {
@@ -57,109 +628,267 @@ impl<A: OwnedAllocator + Default> Default for CheckedAlloc<A> {
     }
 }
 impl<'a, A: OwnedAllocator> CheckedAlloc<A> {
-    fn handle_allocate(&mut self, ptr: *mut u8, requested_size: usize, align: PowerOfTwo) {
-        assert!(align.is_aligned_ptr_mut(ptr),
-                "CheckedAlloc: allocated pointer {:X} not aligned to {}",
-                ptr as usize,
-                align);
-        let real_size = unsafe { self.internal.usable_size(requested_size, align) };
+    // A reallocated block passes its old record, and keeps its sequence number and allocation stack.
+    fn handle_allocate(&mut self,
+                       ptr: *mut u8,
+                       requested_size: usize,
+                       align: PowerOfTwo,
+                       old: Option<&Allocation>)
+                       -> Allocation {
+        if !align.is_aligned_ptr_mut(ptr) {
+            self.report(CheckedAllocErrorKind::Misaligned,
+                        format!("CheckedAlloc: allocated pointer {:X} not aligned to {}", ptr as usize, align));
+        }
+        let real_size = unsafe { self.block_size(requested_size, align) };
         let int = Interval::new(ptr as usize, ptr as usize + real_size - 1);
         match self.allocated.get_first(int) {
             None => {}
             Some((other_int, _)) => {
-                panic!("CheckedAlloc: Allocated interval {:?} while {:?} still live.", int, other_int);
+                self.report(CheckedAllocErrorKind::Overlap,
+                            format!("CheckedAlloc: Allocated interval {:?} while {:?} still live.", int, other_int));
             }
         }
-        self.allocated.fill(int,
-                            Some(Allocation {
-                                ptr: ptr,
-                                requested_size: requested_size,
-                                align: align,
-                            }));
+        let (sequence, stack, suppressed) = match old {
+            Some(old) => (old.sequence, old.stack, old.suppressed),
+            None => {
+                self.next_sequence += 1;
+                (self.next_sequence - 1, self.capture_stack(), false)
+            }
+        };
+        self.freed.fill(int, None);
+        let allocation = Allocation {
+            ptr: ptr,
+            requested_size: requested_size,
+            align: align,
+            sequence: sequence,
+            stack: stack,
+            suppressed: suppressed || self.ignoring > 0,
+        };
+        self.allocated.fill(int, Some(allocation));
+        self.tag(int, Some(self.instance));
+        return allocation;
+    }
+    // Returns None, leaving the block alone, when the free was rejected under a non-panicking policy.
+    unsafe fn handle_deallocate(&mut self, ptr: *mut u8, size: usize, align: PowerOfTwo) -> Option<Allocation> {
+        let checked = self.check_deallocate(ptr, size, align);
+        if let Some((int, allocation)) = checked {
+            self.release(int);
+            self.remember_free(int, allocation);
+            return Some(allocation);
+        }
+        return None;
     }
-    unsafe fn handle_deallocate(&mut self, ptr: *mut u8, size: usize, align: PowerOfTwo) {
-        assert!(align.is_aligned_ptr_mut(ptr), "CheckedAlloc: deallocated unaligned pointer");
-        println!("{:?}", self.allocated);
+    fn release(&mut self, int: Interval<usize>) {
+        self.allocated.fill(int, None);
+        self.tag(int, None);
+    }
+    // Validates a free or reallocation of ptr without changing any state.
+    unsafe fn check_deallocate(&self,
+                               ptr: *mut u8,
+                               size: usize,
+                               align: PowerOfTwo)
+                               -> Option<(Interval<usize>, Allocation)> {
+        if !align.is_aligned_ptr_mut(ptr) {
+            self.report(CheckedAllocErrorKind::Misaligned,
+                        format!("CheckedAlloc: deallocated unaligned pointer {:X}", ptr as usize));
+            return None;
+        }
         match self.allocated.get_interval(ptr as usize) {
             (_, None) => {
-                panic!("CheckedAlloc: Deallocated interval {:?} is not live.",
-                       Interval::new(ptr as usize, ptr as usize + size - 1));
+                let owner = if self.options.tag_instances {
+                    with_registry(|registry| registry.get(ptr as usize))
+                } else {
+                    None
+                };
+                if let Some(owner) = owner {
+                    self.report(CheckedAllocErrorKind::WrongInstance,
+                                format!("CheckedAlloc: block {:X} freed through allocator instance #{} but owned by \
+                                         instance #{}",
+                                        ptr as usize,
+                                        self.instance,
+                                        owner));
+                    return None;
+                }
+                match self.freed.get(ptr as usize) {
+                    Some(freed) if freed.allocation.ptr == ptr => {
+                        self.report(CheckedAllocErrorKind::DoubleFree,
+                                    format!("CheckedAlloc: double free of block allocated as #{} ({} bytes, align \
+                                             {}), previously freed at sequence #{}{}{}",
+                                            freed.allocation.sequence,
+                                            freed.allocation.requested_size,
+                                            freed.allocation.align,
+                                            freed.freed_sequence,
+                                            self.allocated_at(freed.allocation.stack),
+                                            self.freed_at(freed.stack)));
+                    }
+                    _ => {
+                        self.report(CheckedAllocErrorKind::NotLive,
+                                    format!("CheckedAlloc: Deallocated interval {:?} is not live.",
+                                            Interval::new(ptr as usize, ptr as usize + size - 1)));
+                    }
+                }
+                return None;
+            }
+            (_, Some(allocation)) if allocation.ptr != ptr => {
+                self.report(CheckedAllocErrorKind::InteriorPointer,
+                            format!("CheckedAlloc: free of pointer {:X} at offset {} into block #{} ({} bytes, align \
+                                     {}){}",
+                                    ptr as usize,
+                                    ptr as usize - allocation.ptr as usize,
+                                    allocation.sequence,
+                                    allocation.requested_size,
+                                    allocation.align,
+                                    self.allocated_at(allocation.stack)));
+                return None;
             }
             (int, Some(allocation)) => {
-                assert!(align == allocation.align, "CheckedAlloc: different alignment");
-                assert!(allocation.requested_size <= size, "CheckedAlloc: different size ");
-                assert!(size <= self.internal.usable_size(allocation.requested_size, align),
-                        "CheckedAlloc different size ");
-                self.allocated.fill(int, None);
+                if align != allocation.align {
+                    self.report(CheckedAllocErrorKind::WrongAlignment,
+                                format!("CheckedAlloc: different alignment{}", self.allocated_at(allocation.stack)));
+                    return None;
+                }
+                if allocation.requested_size > size || size > self.block_size(allocation.requested_size, align) {
+                    self.report(CheckedAllocErrorKind::WrongSize,
+                                format!("CheckedAlloc: different size {}", self.allocated_at(allocation.stack)));
+                    return None;
+                }
+                if self.options.red_zone > 0 {
+                    self.verify_guards(&allocation);
+                }
+                return Some((int, allocation));
             }
         }
     }
 }
 unsafe impl<A: OwnedAllocator> OwnedAllocator for CheckedAlloc<A> {
     unsafe fn allocate(&mut self, size: usize, align: PowerOfTwo) -> *mut u8 {
-        assert!(size > 0, "CheckedAlloc");
-        let ret = self.internal.allocate(size, align);
-        if ret.is_null() {
-            return ret;
+        if !self.check_size(size, "allocate") {
+            return ptr::null_mut();
+        }
+        let front = self.front_pad(align) as isize;
+        let outer_size = self.outer_size(size, align);
+        let inner = self.internal.allocate(outer_size, align);
+        if inner.is_null() {
+            return inner;
         }
-        self.handle_allocate(ret, size, align);
+        let ret = inner.offset(front);
+        self.fill_guards(ret, size, align);
+        let allocation = self.handle_allocate(ret, size, align, None);
+        self.fire_watches(WatchEvent::Allocate, None, &allocation);
         return ret;
     }
     unsafe fn reallocate(&mut self, ptr: *mut u8, old_size: usize, size: usize, align: PowerOfTwo) -> *mut u8 {
-        assert!(size > 0, "CheckedAlloc");
-        self.handle_deallocate(ptr, old_size, align);
-        let ret = self.internal.reallocate(ptr, old_size, size, align);
-        if ret.is_null() {
-            self.handle_allocate(ret, old_size, align);
-        } else {
-            self.handle_allocate(ret, size, align);
+        if !self.check_size(size, "reallocate") {
+            return ptr::null_mut();
         }
+        let (int, old) = match self.check_deallocate(ptr, old_size, align) {
+            Some(checked) => checked,
+            None => return ptr::null_mut(),
+        };
+        let front = self.front_pad(align) as isize;
+        let (old_outer_size, outer_size) = (self.outer_size(old_size, align), self.outer_size(size, align));
+        let inner = self.internal.reallocate(ptr.offset(-front), old_outer_size, outer_size, align);
+        if inner.is_null() {
+            // The inner allocator still owns the block at ptr, which stays live as it was.
+            return inner;
+        }
+        let ret = inner.offset(front);
+        self.release(int);
+        if ret != ptr {
+            self.remember_free(int, old);
+        }
+        self.fill_guards(ret, size, align);
+        let allocation = self.handle_allocate(ret, size, align, Some(&old));
+        self.fire_watches(WatchEvent::Reallocate, Some(&old), &allocation);
         return ret;
     }
     unsafe fn reallocate_inplace(&mut self, ptr: *mut u8, old_size: usize, size: usize, align: PowerOfTwo) -> usize {
-        assert!(size > 0, "CheckedAlloc");
-        self.handle_deallocate(ptr, old_size, align);
-        let actual_size = self.internal.reallocate_inplace(ptr, old_size, size, align);
-        assert!(actual_size == size || actual_size == old_size, "CheckedAlloc");
-        self.handle_allocate(ptr, actual_size, align);
-        return actual_size;
+        if !self.check_size(size, "reallocate_inplace") {
+            return old_size;
+        }
+        let (int, old) = match self.check_deallocate(ptr, old_size, align) {
+            Some(checked) => checked,
+            None => return old_size,
+        };
+        let front = self.front_pad(align);
+        let (old_outer_size, outer_size) = (self.outer_size(old_size, align), self.outer_size(size, align));
+        let actual_outer_size = self.internal
+            .reallocate_inplace(ptr.offset(-(front as isize)), old_outer_size, outer_size, align);
+        if actual_outer_size != outer_size && actual_outer_size != old_outer_size {
+            self.report(CheckedAllocErrorKind::BadBackend,
+                        format!("CheckedAlloc: reallocate_inplace of {:X} from {} to {} bytes returned {}",
+                                ptr as usize,
+                                old_outer_size,
+                                outer_size,
+                                actual_outer_size));
+        }
+        if actual_outer_size != outer_size {
+            return old_size;
+        }
+        self.release(int);
+        self.fill_guards(ptr, size, align);
+        let allocation = self.handle_allocate(ptr, size, align, Some(&old));
+        self.fire_watches(WatchEvent::Reallocate, Some(&old), &allocation);
+        return size;
     }
     unsafe fn deallocate(&mut self, ptr: *mut u8, old_size: usize, align: PowerOfTwo) {
-        assert!(old_size > 0, "CheckedAlloc");
-        self.handle_deallocate(ptr, old_size, align);
-        self.internal.deallocate(ptr, old_size, align);
+        if !self.check_size(old_size, "deallocate") {
+            return;
+        }
+        let old = match self.handle_deallocate(ptr, old_size, align) {
+            Some(old) => old,
+            None => return,
+        };
+        self.fire_watches(WatchEvent::Free, None, &old);
+        let front = self.front_pad(align);
+        let outer_size = self.outer_size(old_size, align);
+        self.internal.deallocate(ptr.offset(-(front as isize)), outer_size, align);
     }
     unsafe fn extendable_size(&self, ptr: *mut u8, old_size: usize, align: PowerOfTwo) -> usize {
-        assert!(old_size > 0, "CheckedAlloc");
+        if !self.check_size(old_size, "extendable_size") || self.options.red_zone > 0 {
+            return old_size;
+        }
         let ret = self.internal.extendable_size(ptr, old_size, align);
-        assert!(ret >= old_size);
+        if ret < old_size {
+            self.report(CheckedAllocErrorKind::BadBackend,
+                        format!("CheckedAlloc: extendable_size of {:X} ({} bytes) returned {}",
+                                ptr as usize,
+                                old_size,
+                                ret));
+            return old_size;
+        }
         return ret;
     }
     unsafe fn usable_size(&self, size: usize, align: PowerOfTwo) -> usize {
-        assert!(size > 0, "CheckedAlloc");
+        if !self.check_size(size, "usable_size") || self.options.red_zone > 0 {
+            return size;
+        }
         let ret = self.internal.usable_size(size, align);
-        assert!(ret >= size, "CheckedAlloc");
-        assert!(align.is_aligned_size(ret), "CheckedAlloc");
+        if ret < size || !align.is_aligned_size(ret) {
+            self.report(CheckedAllocErrorKind::BadBackend,
+                        format!("CheckedAlloc: usable_size of {} bytes, align {} returned {}", size, align, ret));
+            return size;
+        }
         return ret;
     }
 }
 
 impl<A: OwnedAllocator> Drop for CheckedAlloc<A> {
     fn drop(&mut self) {
-        if !self.options.ignore_leaks && !thread::panicking() {
-            let mut count = 0;
-            let mut bytes = 0;
-            for (int, allocation) in self.allocated.iter(0) {
-                match allocation {
-                    Some(_) => {
-                        count += 1;
-                        bytes += int.len().unwrap();
-                    }
-                    None => {}
-                }
+        // Untag first so that a panicking leak report can't leave stale owners in the registry.
+        if self.options.tag_instances {
+            let live: Vec<Interval<usize>> = self.allocated
+                .iter(0)
+                .filter(|&(_, allocation)| allocation.is_some())
+                .map(|(int, _)| int)
+                .collect();
+            for int in live {
+                self.tag(int, None);
             }
-            if count > 0 {
-                panic!("LeakChecker: {} bytes in {} blocks leaked.", bytes, count);
+        }
+        if !self.options.ignore_leaks && !thread::panicking() {
+            if let Some(report) = self.leak_report() {
+                self.report(CheckedAllocErrorKind::Leak, report);
             }
         }
     }
@@ -216,7 +945,9 @@ fn test_good_backend() {
                                                               ]),
                                                        Default::default()));
         (&alloc).allocate(1, align(1));
-        let b1 = (&alloc).reallocate((&alloc).allocate(1, align(1)), 1, 10, align(1));
+        let b1 = (&alloc).allocate(1, align(1));
+        assert!((&alloc).reallocate(b1, 1, 10, align(1)).is_null());
+        assert_eq!(b1, (*alloc.get()).leaks()[0].ptr);
         let b2 = (&alloc).allocate(1, align(1));
         (&alloc).deallocate(b1, 1, align(1));
         (&alloc).deallocate(b2, 1, align(1));
@@ -241,3 +972,250 @@ fn test_allocate_leak() {
         (&alloc).allocate(1, align(1));
     }
 }
+#[test]
+#[should_panic(expected = "LeakChecker: 3 bytes in 3 blocks leaked.\n3 bytes in 3 blocks\nallocated at:")]
+fn test_leak_stacks() {
+    unsafe {
+        let options = CheckedAllocOptions { capture_stacks: true, ..Default::default() };
+        let alloc = SharedAlloc::new(CheckedAlloc::new(MockAlloc::new(vec![MockAllocResult::Allocate(1 as *mut u8),
+                                                                           MockAllocResult::Allocate(2 as *mut u8),
+                                                                           MockAllocResult::Allocate(3 as *mut u8)]),
+                                                       options));
+        for _ in 0..3 {
+            (&alloc).allocate(1, align(1));
+        }
+    }
+}
+#[test]
+fn test_red_zone() {
+    unsafe {
+        let options = CheckedAllocOptions { red_zone: 16, ..Default::default() };
+        let mut alloc = CheckedAlloc::new(HeapAlloc, options);
+        let ptr = alloc.allocate(10, align(8));
+        assert!(align(8).is_aligned_ptr_mut(ptr));
+        ptr::write_bytes(ptr, 1, 10);
+        let ptr = alloc.reallocate(ptr, 10, 100, align(8));
+        ptr::write_bytes(ptr, 1, 100);
+        alloc.verify_all();
+        let size = alloc.reallocate_inplace(ptr, 100, 20, align(8));
+        alloc.verify_all();
+        alloc.deallocate(ptr, size, align(8));
+    }
+}
+#[test]
+#[should_panic(expected = "corrupted at offsets [10: 01, 11: 01]")]
+fn test_red_zone_overflow() {
+    unsafe {
+        let options = CheckedAllocOptions { red_zone: 16, ..Default::default() };
+        let mut alloc = CheckedAlloc::new(HeapAlloc, options);
+        let ptr = alloc.allocate(10, align(1));
+        ptr::write_bytes(ptr, 1, 12);
+        alloc.verify_all();
+    }
+}
+#[test]
+fn test_error_callback() {
+    unsafe {
+        let errors = Rc::new(RefCell::new(Vec::new()));
+        let callback_errors = errors.clone();
+        let policy = ErrorPolicy::Callback(Box::new(move |error: &CheckedAllocError| {
+            callback_errors.borrow_mut().push(error.kind)
+        }));
+        let mut alloc = CheckedAlloc::with_policy(HeapAlloc, Default::default(), policy);
+        let first = alloc.allocate(8, align(8));
+        let second = alloc.allocate(16, align(8));
+        alloc.deallocate(first, 8, align(8));
+        alloc.deallocate(first, 8, align(8));
+        alloc.deallocate(second, 16, align(4));
+        assert!(alloc.reallocate(first, 8, 16, align(8)).is_null());
+        assert!(alloc.allocate(0, align(8)).is_null());
+        alloc.deallocate(second, 0, align(8));
+        assert_eq!(vec![CheckedAllocErrorKind::DoubleFree,
+                        CheckedAllocErrorKind::WrongAlignment,
+                        CheckedAllocErrorKind::DoubleFree,
+                        CheckedAllocErrorKind::ZeroSize,
+                        CheckedAllocErrorKind::ZeroSize],
+                   *errors.borrow());
+        assert_eq!(vec![LeakRecord {
+                            ptr: second,
+                            requested_size: 16,
+                            align: align(8),
+                            sequence: 1,
+                        }],
+                   alloc.leaks());
+        alloc.deallocate(second, 16, align(8));
+        assert!(alloc.leaks().is_empty());
+    }
+}
+#[test]
+fn test_snapshot_diff() {
+    unsafe {
+        let mut alloc = CheckedAlloc::new(HeapAlloc, Default::default());
+        let kept = alloc.allocate(8, align(1));
+        let freed = alloc.allocate(16, align(1));
+        let before = alloc.snapshot();
+        alloc.deallocate(freed, 16, align(1));
+        let temporary = alloc.allocate(4, align(1));
+        alloc.deallocate(temporary, 4, align(1));
+        let created = alloc.allocate(32, align(1));
+        let after = alloc.snapshot();
+        let diff = before.diff(&after);
+        assert_eq!(vec![created], diff.created.iter().map(|record| record.ptr).collect::<Vec<_>>());
+        assert_eq!(vec![freed], diff.freed.iter().map(|record| record.ptr).collect::<Vec<_>>());
+        assert_eq!(32, diff.created_bytes());
+        assert_eq!(16, diff.freed_bytes());
+        assert_eq!(2, diff.allocation_count);
+        assert_eq!(2, after.live().len());
+        assert!(after.diff(&alloc.snapshot()).created.is_empty());
+        alloc.deallocate(kept, 8, align(1));
+        alloc.deallocate(created, 32, align(1));
+    }
+}
+#[test]
+#[should_panic(expected = "CheckedAlloc: double free of block allocated as #1 (24 bytes, align 8), previously freed at \
+                           sequence #2")]
+fn test_double_free() {
+    unsafe {
+        let mut alloc = CheckedAlloc::new(HeapAlloc, Default::default());
+        let first = alloc.allocate(8, align(8));
+        let second = alloc.allocate(24, align(8));
+        alloc.deallocate(second, 24, align(8));
+        alloc.deallocate(first, 8, align(8));
+        alloc.deallocate(second, 24, align(8));
+    }
+}
+#[test]
+#[should_panic(expected = "at offset 4 into block #0")]
+fn test_interior_free() {
+    unsafe {
+        let mut alloc = CheckedAlloc::new(HeapAlloc, Default::default());
+        let ptr = alloc.allocate(8, align(4));
+        alloc.deallocate(ptr.offset(4), 4, align(4));
+    }
+}
+#[test]
+fn test_free_history_bound() {
+    unsafe {
+        let options = CheckedAllocOptions { free_history: 1, ..Default::default() };
+        let mut alloc = CheckedAlloc::new(HeapAlloc, options);
+        let first = alloc.allocate(8, align(8));
+        let second = alloc.allocate(8, align(8));
+        let third = alloc.allocate(8, align(8));
+        alloc.deallocate(first, 8, align(8));
+        alloc.deallocate(second, 8, align(8));
+        assert!(alloc.freed.get(first as usize).is_none());
+        assert!(alloc.freed.get(second as usize).is_some());
+        assert_eq!(8, alloc.reallocate_inplace(third, 8, 8, align(8)));
+        assert!(alloc.freed.get(second as usize).is_some());
+        alloc.deallocate(third, 8, align(8));
+    }
+}
+#[test]
+fn test_describe() {
+    unsafe {
+        let mut alloc = CheckedAlloc::new(HeapAlloc, Default::default());
+        let live = alloc.allocate(16, align(8));
+        let freed = alloc.allocate(32, align(8));
+        alloc.deallocate(freed, 32, align(8));
+        assert_eq!(Some(AllocationInfo {
+                       base: live,
+                       requested_size: 16,
+                       align: align(8),
+                       sequence: 0,
+                       offset: 5,
+                       freed_sequence: None,
+                   }),
+                   alloc.describe(live.offset(5)));
+        let info = alloc.describe(freed.offset(31)).unwrap();
+        assert_eq!((freed, 31, Some(2)), (info.base, info.offset, info.freed_sequence));
+        assert_eq!(None, alloc.describe(null_mut()));
+        alloc.deallocate(live, 16, align(8));
+    }
+}
+#[test]
+fn test_watch() {
+    unsafe {
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let callback_events = events.clone();
+        let mut alloc = CheckedAlloc::new(HeapAlloc, Default::default());
+        alloc.watch(Watch::Sequence(1),
+                    WatchAction::Callback(Box::new(move |event: WatchEvent, info: &AllocationInfo| {
+                        callback_events.borrow_mut().push((event, info.sequence))
+                    })));
+        let first = alloc.allocate(8, align(1));
+        let second = alloc.allocate(8, align(1));
+        let second = alloc.reallocate(second, 8, 1000, align(1));
+        alloc.deallocate(first, 8, align(1));
+        alloc.deallocate(second, 1000, align(1));
+        assert_eq!(vec![(WatchEvent::Allocate, 1), (WatchEvent::Reallocate, 1), (WatchEvent::Free, 1)],
+                   *events.borrow());
+    }
+}
+#[test]
+#[should_panic(expected = "hit by Free of block #1")]
+fn test_watch_panic() {
+    unsafe {
+        let mut alloc = CheckedAlloc::new(HeapAlloc, Default::default());
+        alloc.watch(Watch::Size(8), WatchAction::Panic);
+        alloc.clear_watches();
+        let unwatched = alloc.allocate(8, align(1));
+        alloc.deallocate(unwatched, 8, align(1));
+        let ptr = alloc.allocate(24, align(1));
+        alloc.watch(Watch::Pointer(ptr), WatchAction::Panic);
+        alloc.deallocate(ptr, 24, align(1));
+    }
+}
+#[test]
+fn test_wrong_instance() {
+    unsafe {
+        let owners = Rc::new(RefCell::new(Vec::new()));
+        let callback_owners = owners.clone();
+        let options = CheckedAllocOptions { tag_instances: true, ..Default::default() };
+        let policy = ErrorPolicy::Callback(Box::new(move |error: &CheckedAllocError| {
+            callback_owners.borrow_mut().push(error.message.clone())
+        }));
+        let mut first = CheckedAlloc::new(HeapAlloc, options);
+        let mut second = CheckedAlloc::with_policy(HeapAlloc, options, policy);
+        let ptr = first.allocate(8, align(1));
+        second.deallocate(ptr, 8, align(1));
+        assert_eq!(vec![format!("CheckedAlloc: block {:X} freed through allocator instance #{} but owned by instance \
+                                 #{}",
+                                ptr as usize,
+                                second.instance(),
+                                first.instance())],
+                   *owners.borrow());
+        first.deallocate(ptr, 8, align(1));
+    }
+}
+#[test]
+fn test_leak_suppression() {
+    unsafe {
+        let mut alloc: CheckedAlloc<HeapAlloc> = Default::default();
+        alloc.suppress(LeakSuppression::Size(24));
+        alloc.suppress(LeakSuppression::Align(align(64)));
+        let sized = alloc.allocate(24, align(1));
+        let aligned = alloc.allocate(8, align(64));
+        let cached = alloc.ignore_leaks_in(|alloc| alloc.allocate(8, align(1)));
+        let cached = alloc.reallocate(cached, 8, 1000, align(1));
+        assert_eq!(3, alloc.leaks().len());
+        assert!(alloc.leak_report().is_none());
+        let leaked = alloc.allocate(8, align(1));
+        assert!(alloc.leak_report().unwrap().starts_with("LeakChecker: 8 bytes in 1 blocks leaked."));
+        alloc.deallocate(sized, 24, align(1));
+        alloc.deallocate(aligned, 8, align(64));
+        alloc.deallocate(cached, 1000, align(1));
+        alloc.deallocate(leaked, 8, align(1));
+    }
+}
+#[test]
+fn test_load_suppressions() {
+    let path = env::temp_dir().join("checked_alloc_test_load_suppressions.txt");
+    fs::File::create(&path).unwrap().write_all(b"# known leaks\nsize 24\n\nalign 16\nstack cache::insert\n").unwrap();
+    assert_eq!(vec![LeakSuppression::Size(24),
+                    LeakSuppression::Align(align(16)),
+                    LeakSuppression::StackContains("cache::insert".to_string())],
+               LeakSuppression::load(&path).unwrap());
+    fs::File::create(&path).unwrap().write_all(b"align 3\n").unwrap();
+    assert!(LeakSuppression::load(&path).is_err());
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,110 @@
+use std::ptr::null_mut;
+use allocator::OwnedAllocator;
+use util;
+use util::PowerOfTwo;
+#[cfg(test)]
+use checked_alloc::CheckedAlloc;
+#[cfg(test)]
+use heap_alloc::HeapAlloc;
+// One block from the parent: persistent allocations grow up from the front, temporary ones grow down from the back.
+pub struct DoubleEndedArena<A: OwnedAllocator> {
+    allocator: A,
+    begin: *mut u8,
+    front: *mut u8,
+    back: *mut u8,
+    end: *mut u8,
+}
+pub struct FrontMark(*mut u8);
+pub struct BackMark(*mut u8);
+impl<A: OwnedAllocator> DoubleEndedArena<A> {
+    pub unsafe fn new(mut allocator: A, size: usize) -> Self {
+        assert!(size > 0);
+        let begin = allocator.allocate(size, PowerOfTwo::new(1));
+        assert!(!begin.is_null(), "DoubleEndedArena: parent allocation failed");
+        let end = begin.offset(size as isize);
+        return DoubleEndedArena {
+            allocator: allocator,
+            begin: begin,
+            front: begin,
+            back: end,
+            end: end,
+        };
+    }
+    pub fn capacity(&self) -> usize {
+        return util::distance(self.begin, self.end);
+    }
+    pub fn remaining(&self) -> usize {
+        return util::distance(self.front, self.back);
+    }
+    pub unsafe fn allocate_front(&mut self, size: usize, align: PowerOfTwo) -> *mut u8 {
+        let ret = align.align_size(self.front as usize);
+        if ret > self.back as usize || size > (self.back as usize) - ret {
+            return null_mut();
+        }
+        self.front = (ret + size) as *mut u8;
+        return ret as *mut u8;
+    }
+    pub unsafe fn allocate_back(&mut self, size: usize, align: PowerOfTwo) -> *mut u8 {
+        if size > (self.back as usize) - (self.front as usize) {
+            return null_mut();
+        }
+        let ret = ((self.back as usize) - size) & !(align.into() - 1);
+        if ret < self.front as usize {
+            return null_mut();
+        }
+        self.back = ret as *mut u8;
+        return ret as *mut u8;
+    }
+    pub fn front_mark(&self) -> FrontMark {
+        return FrontMark(self.front);
+    }
+    pub fn back_mark(&self) -> BackMark {
+        return BackMark(self.back);
+    }
+    pub fn rollback_front(&mut self, mark: FrontMark) {
+        assert!(self.begin <= mark.0 && mark.0 <= self.front,
+                "DoubleEndedArena: rollback to a stale mark");
+        self.front = mark.0;
+    }
+    pub fn rollback_back(&mut self, mark: BackMark) {
+        assert!(self.back <= mark.0 && mark.0 <= self.end,
+                "DoubleEndedArena: rollback to a stale mark");
+        self.back = mark.0;
+    }
+    pub fn reset_back(&mut self) {
+        self.back = self.end;
+    }
+    pub fn reset(&mut self) {
+        self.front = self.begin;
+        self.back = self.end;
+    }
+}
+impl<A: OwnedAllocator> Drop for DoubleEndedArena<A> {
+    fn drop(&mut self) {
+        unsafe {
+            let size = self.capacity();
+            self.allocator.deallocate(self.begin, size, PowerOfTwo::new(1));
+        }
+    }
+}
+#[test]
+fn double_ended_test() {
+    unsafe {
+        let mut arena = DoubleEndedArena::new(CheckedAlloc::new(HeapAlloc, Default::default()), 64);
+        let persistent = arena.allocate_front(10, PowerOfTwo::new(1));
+        let front = arena.front_mark();
+        let back = arena.back_mark();
+        let temporary = arena.allocate_back(10, PowerOfTwo::new(8));
+        assert!(PowerOfTwo::new(8).is_aligned_ptr_mut(temporary));
+        assert!(temporary >= persistent.offset(10));
+        arena.allocate_front(20, PowerOfTwo::new(4));
+        assert!(arena.allocate_front(40, PowerOfTwo::new(1)).is_null());
+        assert!(arena.allocate_back(40, PowerOfTwo::new(1)).is_null());
+        arena.rollback_back(back);
+        assert!(!arena.allocate_back(30, PowerOfTwo::new(1)).is_null());
+        arena.rollback_front(front);
+        assert_eq!(persistent.offset(10), arena.allocate_front(1, PowerOfTwo::new(1)));
+        arena.reset();
+        assert_eq!(64, arena.remaining());
+    }
+}
@@ -189,6 +189,12 @@ impl<T> Interval<T>
     pub fn len(self) -> Option<T> {
         return (self.back - self.front).checked_add(T::one());
     }
+    pub fn front(self) -> T {
+        return self.front;
+    }
+    pub fn back(self) -> T {
+        return self.back;
+    }
 }
 #[cfg(test)]
 #[derive(PartialEq,Eq,Debug)]
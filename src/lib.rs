@@ -1,5 +1,5 @@
 #![feature(alloc, heap_api, unique, oom,zero_one,test,
-coerce_unsized, unsize,collections, core_intrinsics,collections_range)]
+coerce_unsized, unsize,collections, core_intrinsics,collections_range,const_fn,used,asm)]
 #![cfg_attr(test, feature(reflect_marker))]
 extern crate alloc;
 extern crate core;
@@ -9,6 +9,7 @@ extern crate collections;
 
 pub mod util;
 pub mod interval_map;
+pub mod stack_trace;
 pub mod allocator;
 pub mod heap_alloc;
 pub mod arena_alloc;
@@ -17,5 +18,16 @@ pub mod alloc_box;
 pub mod alloc_raw_vec;
 pub mod alloc_vec;
 pub mod simple_alloc;
+pub mod aligned_alloc;
+pub mod typed_arena;
+pub mod double_ended_arena;
+pub mod ring_alloc;
+pub mod arena_pool;
+pub mod quarantine_alloc;
 // mod alloc_list;
 pub mod alloc_raw_box;
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+pub mod libc_alloc;
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+#[macro_use]
+pub mod malloc_export;
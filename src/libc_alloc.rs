@@ -0,0 +1,72 @@
+use allocator::Allocator;
+use allocator::OwnedAllocator;
+use allocator::ShareableAllocator;
+use std::cmp;
+use std::ptr;
+use util::PowerOfTwo;
+// glibc's internal entry points, which stay reachable when malloc itself is interposed.
+extern "C" {
+    fn __libc_malloc(size: usize) -> *mut u8;
+    fn __libc_memalign(align: usize, size: usize) -> *mut u8;
+    fn __libc_realloc(ptr: *mut u8, size: usize) -> *mut u8;
+    fn __libc_free(ptr: *mut u8);
+}
+pub fn libc_min_alignment() -> PowerOfTwo {
+    return PowerOfTwo::new(2 * ::std::mem::size_of::<usize>());
+}
+pub struct LibcAlloc;
+impl Clone for LibcAlloc {
+    fn clone(&self) -> Self {
+        return LibcAlloc;
+    }
+}
+impl Copy for LibcAlloc {}
+impl Default for LibcAlloc {
+    fn default() -> Self {
+        return LibcAlloc;
+    }
+}
+unsafe impl OwnedAllocator for LibcAlloc {
+    unsafe fn allocate(&mut self, new: usize, align: PowerOfTwo) -> *mut u8 {
+        if align <= libc_min_alignment() {
+            return __libc_malloc(new);
+        } else {
+            return __libc_memalign(align.into(), new);
+        }
+    }
+    unsafe fn reallocate(&mut self, ptr: *mut u8, old_size: usize, new: usize, align: PowerOfTwo) -> *mut u8 {
+        if align <= libc_min_alignment() {
+            return __libc_realloc(ptr, new);
+        }
+        let ret = self.allocate(new, align);
+        if !ret.is_null() {
+            ptr::copy_nonoverlapping(ptr, ret, cmp::min(old_size, new));
+            __libc_free(ptr);
+        }
+        return ret;
+    }
+    unsafe fn reallocate_inplace(&mut self, _ptr: *mut u8, old_size: usize, _new: usize, _align: PowerOfTwo) -> usize {
+        return old_size;
+    }
+    unsafe fn deallocate(&mut self, ptr: *mut u8, _old_size: usize, _align: PowerOfTwo) {
+        __libc_free(ptr);
+    }
+}
+unsafe impl Allocator for LibcAlloc {}
+unsafe impl ShareableAllocator for LibcAlloc {}
+#[test]
+fn libc_alloc_test() {
+    unsafe {
+        let mut alloc = LibcAlloc;
+        for &align in &[1, 8, 64, 4096] {
+            let align = PowerOfTwo::new(align);
+            let ptr = alloc.allocate(10, align);
+            assert!(align.is_aligned_ptr_mut(ptr));
+            *ptr.offset(9) = 42;
+            let ptr = alloc.reallocate(ptr, 10, 1000, align);
+            assert!(align.is_aligned_ptr_mut(ptr));
+            assert_eq!(42, *ptr.offset(9));
+            alloc.deallocate(ptr, 1000, align);
+        }
+    }
+}
@@ -0,0 +1,348 @@
+use std::cell::Cell;
+use std::cell::UnsafeCell;
+use std::cmp;
+use std::env;
+use std::intrinsics;
+use std::io;
+use std::io::Write;
+use std::panic;
+use std::mem::size_of;
+use std::ptr;
+use std::ptr::null_mut;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use allocator::OwnedAllocator;
+use checked_alloc::CheckedAlloc;
+use checked_alloc::CheckedAllocOptions;
+use checked_alloc::ErrorPolicy;
+use libc_alloc::LibcAlloc;
+use libc_alloc::libc_min_alignment;
+use simple_alloc::LoggingAlloc;
+use util::PowerOfTwo;
+#[cfg(test)]
+use heap_alloc::HeapAlloc;
+
+// Exports the C allocation API on top of an allocator stack. The stack must bottom out in LibcAlloc: calls made
+// while the stack is already running on this thread (its own bookkeeping, logging) and calls made after shutdown
+// are served by LibcAlloc directly, and blocks may be freed through either path.
+//
+// So every layer must hand LibcAlloc's pointers through unchanged and keep no block of its own: a free that
+// bypasses the stack must leave it consistent. CheckedAlloc with red_zone != 0, AlignedAlloc and QuarantineAlloc
+// all break this and will corrupt the heap here.
+#[macro_export]
+macro_rules! malloc_exports {
+    ($alloc:ty, $init:expr) => {
+        static MALLOC_EXPORT: $crate::malloc_export::MallocExport<$alloc> =
+            $crate::malloc_export::MallocExport::new($init);
+        #[no_mangle]
+        pub unsafe extern "C" fn malloc(size: usize) -> *mut u8 {
+            return MALLOC_EXPORT.malloc(size, $crate::libc_alloc::libc_min_alignment());
+        }
+        #[no_mangle]
+        pub unsafe extern "C" fn free(ptr: *mut u8) {
+            MALLOC_EXPORT.free(ptr);
+        }
+        #[no_mangle]
+        pub unsafe extern "C" fn calloc(count: usize, size: usize) -> *mut u8 {
+            return MALLOC_EXPORT.calloc(count, size);
+        }
+        #[no_mangle]
+        pub unsafe extern "C" fn realloc(ptr: *mut u8, size: usize) -> *mut u8 {
+            return MALLOC_EXPORT.realloc(ptr, size);
+        }
+        #[no_mangle]
+        pub unsafe extern "C" fn posix_memalign(memptr: *mut *mut u8, align: usize, size: usize) -> i32 {
+            return MALLOC_EXPORT.posix_memalign(memptr, align, size);
+        }
+        #[no_mangle]
+        pub unsafe extern "C" fn aligned_alloc(align: usize, size: usize) -> *mut u8 {
+            return MALLOC_EXPORT.aligned_alloc(align, size);
+        }
+        #[no_mangle]
+        pub unsafe extern "C" fn memalign(align: usize, size: usize) -> *mut u8 {
+            return MALLOC_EXPORT.aligned_alloc(align, size);
+        }
+        #[no_mangle]
+        pub unsafe extern "C" fn valloc(size: usize) -> *mut u8 {
+            return MALLOC_EXPORT.valloc(size);
+        }
+        #[no_mangle]
+        pub unsafe extern "C" fn pvalloc(size: usize) -> *mut u8 {
+            return MALLOC_EXPORT.pvalloc(size);
+        }
+        #[no_mangle]
+        pub unsafe extern "C" fn malloc_usable_size(ptr: *mut u8) -> usize {
+            return MALLOC_EXPORT.malloc_usable_size(ptr);
+        }
+        extern "C" fn malloc_export_shutdown() {
+            unsafe {
+                MALLOC_EXPORT.shutdown();
+            }
+        }
+        #[link_section = ".fini_array"]
+        #[used]
+        static MALLOC_EXPORT_FINI: extern "C" fn() = malloc_export_shutdown;
+    };
+}
+const EINVAL: i32 = 22;
+const ENOMEM: i32 = 12;
+const SC_PAGESIZE: i32 = 30;
+extern "C" {
+    fn sysconf(name: i32) -> isize;
+}
+extern "C" {
+    fn __errno_location() -> *mut i32;
+}
+unsafe fn errno() -> i32 {
+    return *__errno_location();
+}
+unsafe fn set_errno(value: i32) {
+    *__errno_location() = value;
+}
+fn page_size() -> usize {
+    return unsafe { sysconf(SC_PAGESIZE) as usize };
+}
+// Unwinding out of an extern "C" function is undefined, and would leave the lock held besides.
+fn abort_on_panic<R, F: FnOnce() -> R>(f: F) -> R {
+    match panic::catch_unwind(panic::AssertUnwindSafe(f)) {
+        Ok(ret) => return ret,
+        Err(_) => {
+            IN_HOOK.with(|in_hook| in_hook.set(true));
+            let _ = writeln!(io::stderr(), "malloc_export: allocator panicked, aborting");
+            unsafe { intrinsics::abort() }
+        }
+    }
+}
+thread_local!(static IN_HOOK: Cell<bool> = Cell::new(false));
+#[derive(Clone,Copy)]
+struct Header {
+    size: usize,
+    align: PowerOfTwo,
+}
+fn block_align(align: PowerOfTwo) -> PowerOfTwo {
+    return cmp::max(align, PowerOfTwo::new(size_of::<Header>().next_power_of_two()));
+}
+unsafe fn header(ptr: *mut u8) -> *mut Header {
+    return (ptr as *mut Header).offset(-1);
+}
+pub struct MallocExport<A: OwnedAllocator> {
+    lock: AtomicBool,
+    shut_down: AtomicBool,
+    allocator: UnsafeCell<Option<A>>,
+    init: fn() -> A,
+}
+unsafe impl<A: OwnedAllocator> Sync for MallocExport<A> {}
+impl<A: OwnedAllocator> MallocExport<A> {
+    pub const fn new(init: fn() -> A) -> Self {
+        return MallocExport {
+            lock: AtomicBool::new(false),
+            shut_down: AtomicBool::new(false),
+            allocator: UnsafeCell::new(None),
+            init: init,
+        };
+    }
+    unsafe fn with_allocator<R, F>(&self, f: F) -> R
+        where F: FnOnce(&mut OwnedAllocator) -> R
+    {
+        if IN_HOOK.with(|in_hook| in_hook.get()) || self.shut_down.load(Ordering::Acquire) {
+            return f(&mut LibcAlloc);
+        }
+        while self.lock.compare_and_swap(false, true, Ordering::Acquire) {}
+        IN_HOOK.with(|in_hook| in_hook.set(true));
+        let allocator = &mut *self.allocator.get();
+        if allocator.is_none() {
+            *allocator = Some((self.init)());
+        }
+        let ret = abort_on_panic(|| f(allocator.as_mut().unwrap()));
+        IN_HOOK.with(|in_hook| in_hook.set(false));
+        self.lock.store(false, Ordering::Release);
+        return ret;
+    }
+    pub unsafe fn malloc(&self, size: usize, align: PowerOfTwo) -> *mut u8 {
+        let block_align = block_align(align);
+        let total = match size.checked_add(block_align.into()) {
+            Some(total) => total,
+            None => {
+                set_errno(ENOMEM);
+                return null_mut();
+            }
+        };
+        let base = self.with_allocator(|allocator| allocator.allocate(total, block_align));
+        if base.is_null() {
+            set_errno(ENOMEM);
+            return base;
+        }
+        let ret = base.offset(block_align.into() as isize);
+        *header(ret) = Header {
+            size: size,
+            align: align,
+        };
+        return ret;
+    }
+    pub unsafe fn free(&self, ptr: *mut u8) {
+        if ptr.is_null() {
+            return;
+        }
+        let Header { size, align } = *header(ptr);
+        let block_align = block_align(align);
+        let base = ptr.offset(-(block_align.into() as isize));
+        self.with_allocator(|allocator| allocator.deallocate(base, size + block_align.into(), block_align));
+    }
+    pub unsafe fn calloc(&self, count: usize, size: usize) -> *mut u8 {
+        let total = match count.checked_mul(size) {
+            Some(total) => total,
+            None => {
+                set_errno(ENOMEM);
+                return null_mut();
+            }
+        };
+        let ret = self.malloc(total, libc_min_alignment());
+        if !ret.is_null() {
+            ptr::write_bytes(ret, 0, total);
+        }
+        return ret;
+    }
+    pub unsafe fn realloc(&self, ptr: *mut u8, size: usize) -> *mut u8 {
+        if ptr.is_null() {
+            return self.malloc(size, libc_min_alignment());
+        }
+        if size == 0 {
+            self.free(ptr);
+            return null_mut();
+        }
+        let Header { size: old_size, align } = *header(ptr);
+        let block_align = block_align(align);
+        let total = match size.checked_add(block_align.into()) {
+            Some(total) => total,
+            None => {
+                set_errno(ENOMEM);
+                return null_mut();
+            }
+        };
+        let base = ptr.offset(-(block_align.into() as isize));
+        let new_base = self.with_allocator(|allocator| {
+            allocator.reallocate(base, old_size + block_align.into(), total, block_align)
+        });
+        if new_base.is_null() {
+            set_errno(ENOMEM);
+            return new_base;
+        }
+        let ret = new_base.offset(block_align.into() as isize);
+        *header(ret) = Header {
+            size: size,
+            align: align,
+        };
+        return ret;
+    }
+    pub unsafe fn posix_memalign(&self, memptr: *mut *mut u8, align: usize, size: usize) -> i32 {
+        if !align.is_power_of_two() || align % size_of::<usize>() != 0 {
+            return EINVAL;
+        }
+        // Failure is reported through the return value; errno is left as it was.
+        let saved_errno = errno();
+        let ret = self.malloc(size, PowerOfTwo::new(align));
+        if ret.is_null() {
+            set_errno(saved_errno);
+            return ENOMEM;
+        }
+        *memptr = ret;
+        return 0;
+    }
+    pub unsafe fn aligned_alloc(&self, align: usize, size: usize) -> *mut u8 {
+        if !align.is_power_of_two() {
+            set_errno(EINVAL);
+            return null_mut();
+        }
+        return self.malloc(size, PowerOfTwo::new(align));
+    }
+    pub unsafe fn valloc(&self, size: usize) -> *mut u8 {
+        return self.malloc(size, PowerOfTwo::new(page_size()));
+    }
+    pub unsafe fn pvalloc(&self, size: usize) -> *mut u8 {
+        let page = PowerOfTwo::new(page_size());
+        let size = match size.checked_add(page.into() - 1) {
+            Some(size) => page.align_size(size),
+            None => {
+                set_errno(ENOMEM);
+                return null_mut();
+            }
+        };
+        return self.malloc(cmp::max(size, page.into()), page);
+    }
+    pub unsafe fn malloc_usable_size(&self, ptr: *mut u8) -> usize {
+        if ptr.is_null() {
+            return 0;
+        }
+        return (*header(ptr)).size;
+    }
+    pub unsafe fn shutdown(&self) {
+        while self.lock.compare_and_swap(false, true, Ordering::Acquire) {}
+        let allocator = (*self.allocator.get()).take();
+        self.shut_down.store(true, Ordering::Release);
+        self.lock.store(false, Ordering::Release);
+        IN_HOOK.with(|in_hook| in_hook.set(true));
+        abort_on_panic(|| drop(allocator));
+        IN_HOOK.with(|in_hook| in_hook.set(false));
+    }
+}
+// C programs routinely leak at exit (stdio buffers, for one). A panic cannot cross the C ABI, so the preload
+// stacks log their reports instead.
+fn preload_options() -> CheckedAllocOptions {
+    return CheckedAllocOptions {
+        ignore_leaks: env::var_os("ALLOCATOR_IGNORE_LEAKS").is_some(),
+        ..Default::default()
+    };
+}
+pub fn preload_checked_alloc() -> CheckedAlloc<LibcAlloc> {
+    return CheckedAlloc::with_policy(LibcAlloc, preload_options(), ErrorPolicy::Log);
+}
+pub fn preload_logging_alloc() -> LoggingAlloc<LibcAlloc> {
+    return LoggingAlloc::new(LibcAlloc);
+}
+pub fn preload_checked_logging_alloc() -> CheckedAlloc<LoggingAlloc<LibcAlloc>> {
+    return CheckedAlloc::with_policy(LoggingAlloc::new(LibcAlloc), preload_options(), ErrorPolicy::Log);
+}
+#[cfg(all(feature = "preload_checked", not(feature = "preload_logging")))]
+malloc_exports!(CheckedAlloc<LibcAlloc>, preload_checked_alloc);
+#[cfg(all(feature = "preload_logging", not(feature = "preload_checked")))]
+malloc_exports!(LoggingAlloc<LibcAlloc>, preload_logging_alloc);
+#[cfg(all(feature = "preload_checked", feature = "preload_logging"))]
+malloc_exports!(CheckedAlloc<LoggingAlloc<LibcAlloc>>, preload_checked_logging_alloc);
+#[cfg(test)]
+fn test_alloc() -> CheckedAlloc<HeapAlloc> {
+    return Default::default();
+}
+#[test]
+fn malloc_export_test() {
+    unsafe {
+        let export = MallocExport::new(test_alloc);
+        let ptr = export.malloc(10, libc_min_alignment());
+        assert_eq!(10, export.malloc_usable_size(ptr));
+        *ptr.offset(9) = 42;
+        let ptr = export.realloc(ptr, 100);
+        assert_eq!(42, *ptr.offset(9));
+        assert_eq!(100, export.malloc_usable_size(ptr));
+        let zeroed = export.calloc(4, 4);
+        for i in 0..16 {
+            assert_eq!(0, *zeroed.offset(i));
+        }
+        let mut aligned = null_mut();
+        assert_eq!(0, export.posix_memalign(&mut aligned, 4096, 10));
+        assert!(PowerOfTwo::new(4096).is_aligned_ptr_mut(aligned));
+        assert_eq!(EINVAL, export.posix_memalign(&mut aligned, 12, 10));
+        assert!(export.aligned_alloc(3, 10).is_null());
+        assert_eq!(EINVAL, errno());
+        assert!(export.calloc(usize::max_value(), 2).is_null());
+        assert_eq!(ENOMEM, errno());
+        let page = PowerOfTwo::new(page_size());
+        let paged = export.pvalloc(1);
+        assert!(page.is_aligned_ptr_mut(paged));
+        assert_eq!(page.into(), export.malloc_usable_size(paged));
+        export.free(paged);
+        export.free(ptr);
+        export.free(zeroed);
+        export.free(aligned);
+        export.free(null_mut());
+        export.shutdown();
+    }
+}
@@ -0,0 +1,220 @@
+use std::cmp;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::ptr;
+use std::thread;
+use allocator::OwnedAllocator;
+use allocator::ShareableAllocator;
+use checked_alloc::CheckedAllocErrorKind;
+use checked_alloc::ErrorPolicy;
+use simple_alloc;
+use stack_trace::StackTrace;
+use util::PowerOfTwo;
+#[cfg(test)]
+use checked_alloc::CheckedAlloc;
+#[cfg(test)]
+use checked_alloc::CheckedAllocError;
+#[cfg(test)]
+use heap_alloc::HeapAlloc;
+#[cfg(test)]
+use std::cell::RefCell;
+#[cfg(test)]
+use std::rc::Rc;
+pub struct QuarantineOptions {
+    // Freed bytes held back from the inner allocator before the oldest block is released.
+    pub capacity: usize,
+    pub capture_stacks: bool,
+    // Write-after-free reports are raised as CheckedAllocErrorKind::WriteAfterFree.
+    pub error_policy: ErrorPolicy,
+}
+impl Default for QuarantineOptions {
+    fn default() -> Self {
+        return QuarantineOptions {
+            capacity: 1 << 20,
+            capture_stacks: false,
+            error_policy: ErrorPolicy::Panic,
+        };
+    }
+}
+struct QuarantinedBlock {
+    ptr: *mut u8,
+    size: usize,
+    // The usable size of the block, all of which is poisoned.
+    poisoned: usize,
+    align: PowerOfTwo,
+    allocated_at: Option<StackTrace>,
+    freed_at: Option<StackTrace>,
+}
+// Delays frees so that writes through dangling pointers land in poisoned memory, where they are caught when the
+// block finally leaves the quarantine.
+pub struct QuarantineAlloc<A: OwnedAllocator> {
+    allocator: A,
+    options: QuarantineOptions,
+    quarantine: VecDeque<QuarantinedBlock>,
+    quarantined_bytes: usize,
+    allocation_sites: HashMap<usize, StackTrace>,
+    poison: Vec<u8>,
+}
+impl<A: OwnedAllocator> QuarantineAlloc<A> {
+    pub fn new(allocator: A, options: QuarantineOptions) -> Self {
+        return QuarantineAlloc {
+            allocator: allocator,
+            options: options,
+            quarantine: VecDeque::new(),
+            quarantined_bytes: 0,
+            allocation_sites: HashMap::new(),
+            poison: simple_alloc::dead_beef(),
+        };
+    }
+    pub fn quarantined_bytes(&self) -> usize {
+        return self.quarantined_bytes;
+    }
+    // Checks and releases every quarantined block.
+    pub fn flush(&mut self) {
+        while let Some(block) = self.quarantine.pop_front() {
+            self.release(block);
+        }
+    }
+    fn record_allocation(&mut self, ptr: *mut u8) {
+        if self.options.capture_stacks {
+            self.allocation_sites.insert(ptr as usize, StackTrace::capture());
+        }
+    }
+    unsafe fn quarantine(&mut self, ptr: *mut u8, size: usize, align: PowerOfTwo) {
+        let poisoned = self.allocator.usable_size(size, align);
+        simple_alloc::fill_pattern(ptr, poisoned, &self.poison);
+        let allocated_at = self.allocation_sites.remove(&(ptr as usize));
+        let freed_at = if self.options.capture_stacks {
+            Some(StackTrace::capture())
+        } else {
+            None
+        };
+        self.quarantine.push_back(QuarantinedBlock {
+            ptr: ptr,
+            size: size,
+            poisoned: poisoned,
+            align: align,
+            allocated_at: allocated_at,
+            freed_at: freed_at,
+        });
+        self.quarantined_bytes += size;
+        while self.quarantined_bytes > self.options.capacity {
+            let block = self.quarantine.pop_front().unwrap();
+            self.release(block);
+        }
+    }
+    fn release(&mut self, block: QuarantinedBlock) {
+        self.quarantined_bytes -= block.size;
+        unsafe {
+            let changed: Vec<String> = simple_alloc::check_pattern(block.ptr, block.poisoned, &self.poison)
+                .into_iter()
+                .map(|i| format!("{}: {:02X}", i, *block.ptr.offset(i as isize)))
+                .collect();
+            if !changed.is_empty() {
+                let mut report = format!("QuarantineAlloc: write after free to block {:X} ({} bytes) at offsets [{}]",
+                                         block.ptr as usize,
+                                         block.size,
+                                         changed.join(", "));
+                if let Some(ref allocated_at) = block.allocated_at {
+                    report.push_str(&format!("\nallocated at:\n{}", allocated_at));
+                }
+                if let Some(ref freed_at) = block.freed_at {
+                    report.push_str(&format!("\nfreed at:\n{}", freed_at));
+                }
+                self.options.error_policy.report(CheckedAllocErrorKind::WriteAfterFree, report);
+            }
+            self.allocator.deallocate(block.ptr, block.size, block.align);
+        }
+    }
+}
+unsafe impl<A: OwnedAllocator> OwnedAllocator for QuarantineAlloc<A> {
+    unsafe fn allocate(&mut self, size: usize, align: PowerOfTwo) -> *mut u8 {
+        let ret = self.allocator.allocate(size, align);
+        if !ret.is_null() {
+            self.record_allocation(ret);
+        }
+        return ret;
+    }
+    unsafe fn reallocate(&mut self, ptr: *mut u8, old_size: usize, size: usize, align: PowerOfTwo) -> *mut u8 {
+        if self.reallocate_inplace(ptr, old_size, size, align) == size {
+            return ptr;
+        }
+        // Moving blocks go through the quarantine too, so the old address is not handed out again right away.
+        let ret = self.allocator.allocate(size, align);
+        if !ret.is_null() {
+            ptr::copy_nonoverlapping(ptr, ret, cmp::min(old_size, size));
+            self.record_allocation(ret);
+            self.quarantine(ptr, old_size, align);
+        }
+        return ret;
+    }
+    unsafe fn reallocate_inplace(&mut self, ptr: *mut u8, old_size: usize, size: usize, align: PowerOfTwo) -> usize {
+        return self.allocator.reallocate_inplace(ptr, old_size, size, align);
+    }
+    unsafe fn deallocate(&mut self, ptr: *mut u8, old_size: usize, align: PowerOfTwo) {
+        self.quarantine(ptr, old_size, align);
+    }
+    unsafe fn extendable_size(&self, ptr: *mut u8, old_size: usize, align: PowerOfTwo) -> usize {
+        return self.allocator.extendable_size(ptr, old_size, align);
+    }
+    unsafe fn usable_size(&self, size: usize, align: PowerOfTwo) -> usize {
+        return self.allocator.usable_size(size, align);
+    }
+}
+impl<A: OwnedAllocator> Drop for QuarantineAlloc<A> {
+    fn drop(&mut self) {
+        if !thread::panicking() {
+            self.flush();
+        }
+    }
+}
+unsafe impl<A: ShareableAllocator> ShareableAllocator for QuarantineAlloc<A> {}
+#[test]
+fn quarantine_test() {
+    unsafe {
+        let options = QuarantineOptions { capacity: 100, ..Default::default() };
+        let mut alloc = QuarantineAlloc::new(CheckedAlloc::new(HeapAlloc, Default::default()), options);
+        let first = alloc.allocate(60, PowerOfTwo::new(1));
+        let second = alloc.allocate(60, PowerOfTwo::new(1));
+        alloc.deallocate(first, 60, PowerOfTwo::new(1));
+        assert_eq!(60, alloc.quarantined_bytes());
+        let second = alloc.reallocate(second, 60, 10000, PowerOfTwo::new(1));
+        assert_eq!(60, alloc.quarantined_bytes());
+        alloc.deallocate(second, 10000, PowerOfTwo::new(1));
+        assert_eq!(0, alloc.quarantined_bytes());
+    }
+}
+#[test]
+#[should_panic(expected = "QuarantineAlloc: write after free")]
+fn quarantine_write_after_free_test() {
+    unsafe {
+        let options = QuarantineOptions { capture_stacks: true, ..Default::default() };
+        let mut alloc = QuarantineAlloc::new(HeapAlloc, options);
+        let ptr = alloc.allocate(8, PowerOfTwo::new(1));
+        alloc.deallocate(ptr, 8, PowerOfTwo::new(1));
+        *ptr.offset(3) = 0;
+        alloc.flush();
+    }
+}
+#[test]
+fn quarantine_error_policy_test() {
+    unsafe {
+        let errors = Rc::new(RefCell::new(Vec::new()));
+        let errors_clone = errors.clone();
+        let options = QuarantineOptions {
+            error_policy: ErrorPolicy::Callback(Box::new(move |error: &CheckedAllocError| {
+                errors_clone.borrow_mut().push(error.kind);
+            })),
+            ..Default::default()
+        };
+        let mut alloc = QuarantineAlloc::new(CheckedAlloc::new(HeapAlloc, Default::default()), options);
+        let ptr = alloc.allocate(5, PowerOfTwo::new(1));
+        let usable = alloc.usable_size(5, PowerOfTwo::new(1));
+        alloc.deallocate(ptr, 5, PowerOfTwo::new(1));
+        // Past the requested size but inside the usable size.
+        *ptr.offset(usable as isize - 1) ^= 0xFF;
+        alloc.flush();
+        assert_eq!(vec![CheckedAllocErrorKind::WriteAfterFree], *errors.borrow());
+        assert_eq!(0, alloc.quarantined_bytes());
+    }
+}
@@ -0,0 +1,188 @@
+use std::cmp;
+use std::collections::VecDeque;
+use std::ptr;
+use std::ptr::null_mut;
+use allocator::OwnedAllocator;
+use allocator::ShareableAllocator;
+use util::PowerOfTwo;
+#[cfg(test)]
+use checked_alloc::CheckedAlloc;
+#[cfg(test)]
+use heap_alloc::HeapAlloc;
+struct RingRecord {
+    data: *mut u8,
+    start: usize,
+    end: usize,
+    freed: bool,
+}
+// Allocates from a fixed block in FIFO order. Space is reclaimed from the oldest allocation forward; an allocation
+// freed out of order stays reserved until everything older than it has been freed too.
+pub struct RingAlloc<A: OwnedAllocator> {
+    allocator: A,
+    block: *mut u8,
+    capacity: usize,
+    records: VecDeque<RingRecord>,
+}
+impl<A: OwnedAllocator> RingAlloc<A> {
+    pub unsafe fn new(mut allocator: A, capacity: usize) -> Self {
+        assert!(capacity > 0);
+        let block = allocator.allocate(capacity, PowerOfTwo::new(1));
+        assert!(!block.is_null(), "RingAlloc: parent allocation failed");
+        return RingAlloc {
+            allocator: allocator,
+            block: block,
+            capacity: capacity,
+            records: VecDeque::new(),
+        };
+    }
+    pub fn capacity(&self) -> usize {
+        return self.capacity;
+    }
+    pub fn live_count(&self) -> usize {
+        return self.records.len();
+    }
+    fn head(&self) -> usize {
+        return self.records.back().map_or(0, |record| record.end);
+    }
+    fn tail(&self) -> usize {
+        return self.records.front().map_or(0, |record| record.start);
+    }
+    fn wrapped(&self) -> bool {
+        match (self.records.front(), self.records.back()) {
+            (Some(oldest), Some(newest)) => return newest.start < oldest.start,
+            _ => return false,
+        }
+    }
+    // Where the newest record may extend to.
+    fn limit(&self) -> usize {
+        if self.wrapped() {
+            return self.tail();
+        } else {
+            return self.capacity;
+        }
+    }
+    fn fit(&self, start: usize, limit: usize, size: usize, align: PowerOfTwo) -> Option<RingRecord> {
+        let data = align.align_size(self.block as usize + start) - self.block as usize;
+        if data > limit || size > limit - data {
+            return None;
+        }
+        return Some(RingRecord {
+            data: (self.block as usize + data) as *mut u8,
+            start: start,
+            end: data + size,
+            freed: false,
+        });
+    }
+    // Freed records wait in the ring until everything older is freed, so they are skipped here.
+    fn position(&self, ptr: *mut u8) -> usize {
+        match self.records.iter().position(|record| record.data == ptr && !record.freed) {
+            Some(index) => index,
+            None if self.records.iter().any(|record| record.data == ptr) => {
+                panic!("RingAlloc: pointer {:X} freed twice", ptr as usize)
+            }
+            None => panic!("RingAlloc: pointer {:X} is not live", ptr as usize),
+        }
+    }
+}
+unsafe impl<A: OwnedAllocator> OwnedAllocator for RingAlloc<A> {
+    unsafe fn allocate(&mut self, size: usize, align: PowerOfTwo) -> *mut u8 {
+        let head = self.head();
+        let mut record = self.fit(head, self.limit(), size, align);
+        if record.is_none() && !self.wrapped() {
+            let tail = self.tail();
+            record = self.fit(0, tail, size, align);
+        }
+        match record {
+            Some(record) => {
+                let ret = record.data;
+                self.records.push_back(record);
+                return ret;
+            }
+            None => return null_mut(),
+        }
+    }
+    unsafe fn reallocate(&mut self, ptr: *mut u8, old_size: usize, new_size: usize, align: PowerOfTwo) -> *mut u8 {
+        if self.reallocate_inplace(ptr, old_size, new_size, align) == new_size {
+            return ptr;
+        }
+        let ret = self.allocate(new_size, align);
+        if !ret.is_null() {
+            ptr::copy_nonoverlapping(ptr, ret, cmp::min(old_size, new_size));
+            self.deallocate(ptr, old_size, align);
+        }
+        return ret;
+    }
+    unsafe fn reallocate_inplace(&mut self,
+                                 ptr: *mut u8,
+                                 old_size: usize,
+                                 new_size: usize,
+                                 _align: PowerOfTwo)
+                                 -> usize {
+        let index = self.position(ptr);
+        let data = (ptr as usize) - (self.block as usize);
+        if index + 1 == self.records.len() && new_size <= self.limit() - data {
+            self.records[index].end = data + new_size;
+            return new_size;
+        }
+        return old_size;
+    }
+    unsafe fn deallocate(&mut self, ptr: *mut u8, _old_size: usize, _align: PowerOfTwo) {
+        let index = self.position(ptr);
+        self.records[index].freed = true;
+        while self.records.front().map_or(false, |record| record.freed) {
+            self.records.pop_front();
+        }
+    }
+    unsafe fn extendable_size(&self, ptr: *mut u8, old_size: usize, _align: PowerOfTwo) -> usize {
+        if self.records.back().map_or(false, |record| record.data == ptr && !record.freed) {
+            return self.limit() - ((ptr as usize) - (self.block as usize));
+        }
+        return old_size;
+    }
+}
+impl<A: OwnedAllocator> Drop for RingAlloc<A> {
+    fn drop(&mut self) {
+        unsafe {
+            self.allocator.deallocate(self.block, self.capacity, PowerOfTwo::new(1));
+        }
+    }
+}
+unsafe impl<A: ShareableAllocator> ShareableAllocator for RingAlloc<A> {}
+#[test]
+fn ring_test() {
+    unsafe {
+        let mut ring = RingAlloc::new(CheckedAlloc::new(HeapAlloc, Default::default()), 64);
+        let align = PowerOfTwo::new(1);
+        let first = ring.allocate(20, align);
+        let second = ring.allocate(20, align);
+        let third = ring.allocate(20, align);
+        assert!(ring.allocate(20, align).is_null());
+        ring.deallocate(second, 20, align);
+        assert!(ring.allocate(20, align).is_null());
+        ring.deallocate(first, 20, align);
+        let fourth = ring.allocate(20, align);
+        assert_eq!(first, fourth);
+        let fifth = ring.allocate(20, align);
+        assert_eq!(second, fifth);
+        assert!(ring.allocate(1, align).is_null());
+        assert_eq!(20, ring.reallocate_inplace(fifth, 20, 30, align));
+        ring.deallocate(third, 20, align);
+        assert_eq!(30, ring.reallocate_inplace(fifth, 20, 30, align));
+        ring.deallocate(fourth, 20, align);
+        ring.deallocate(fifth, 30, align);
+        assert_eq!(0, ring.live_count());
+        assert_eq!(first, ring.allocate(64, align));
+    }
+}
+#[test]
+#[should_panic(expected = "freed twice")]
+fn ring_double_free_test() {
+    unsafe {
+        let mut ring = RingAlloc::new(CheckedAlloc::new(HeapAlloc, Default::default()), 64);
+        let align = PowerOfTwo::new(1);
+        let _first = ring.allocate(20, align);
+        let second = ring.allocate(20, align);
+        ring.deallocate(second, 20, align);
+        ring.deallocate(second, 20, align);
+    }
+}
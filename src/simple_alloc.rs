@@ -4,6 +4,13 @@ use std::mem::size_of;
 use std::ptr::null_mut;
 use allocator::ShareableAllocator;
 use std::ptr::write;
+use std::ptr::copy_nonoverlapping;
+use std::cmp;
+use util::distance;
+#[cfg(test)]
+use checked_alloc::CheckedAlloc;
+#[cfg(test)]
+use heap_alloc::HeapAlloc;
 pub enum MockAllocResult {
     Allocate(*mut u8),
     Reallocate(*mut u8),
@@ -45,6 +52,27 @@ unsafe impl OwnedAllocator for MockAlloc {
     }
 }
 unsafe impl ShareableAllocator for MockAlloc {}
+// The default DeadBeefAllocator pattern: DE AD BE EF repeated over one word.
+pub fn dead_beef() -> Vec<u8> {
+    let mut beef = Vec::new();
+    while beef.len() < size_of::<usize>() {
+        beef.push(0xDE);
+        beef.push(0xAD);
+        beef.push(0xBE);
+        beef.push(0xEF);
+    }
+    beef.resize(size_of::<usize>(), 0);
+    return beef;
+}
+pub unsafe fn fill_pattern(ptr: *mut u8, size: usize, pattern: &[u8]) {
+    for i in 0..size {
+        write(ptr.offset(i as isize), pattern[i % pattern.len()]);
+    }
+}
+// Offsets of the bytes that no longer match a pattern written by fill_pattern.
+pub unsafe fn check_pattern(ptr: *const u8, size: usize, pattern: &[u8]) -> Vec<usize> {
+    return (0..size).filter(|&i| *ptr.offset(i as isize) != pattern[i % pattern.len()]).collect();
+}
 pub struct DeadBeefAllocator<A: OwnedAllocator> {
     allocator: A,
     beef: Vec<u8>,
@@ -57,22 +85,12 @@ impl<A: OwnedAllocator> DeadBeefAllocator<A> {
         };
     }
     unsafe fn fill(&self, ptr: *mut u8, size: usize) {
-        for i in 0..(size as usize) {
-            write(ptr.offset(i as isize), self.beef[i % self.beef.len()]);
-        }
+        fill_pattern(ptr, size, &self.beef);
     }
 }
 impl<A: Default + OwnedAllocator> Default for DeadBeefAllocator<A> {
     fn default() -> Self {
-        let mut beef = Vec::new();
-        while beef.len() < size_of::<usize>() {
-            beef.push(0xDE);
-            beef.push(0xAD);
-            beef.push(0xBE);
-            beef.push(0xEF);
-        }
-        beef.resize(size_of::<usize>(), 0);
-        return Self::new(A::default(), beef);
+        return Self::new(A::default(), dead_beef());
     }
 }
 unsafe impl<A: OwnedAllocator> OwnedAllocator for DeadBeefAllocator<A> {
@@ -159,39 +177,103 @@ pub struct BlockAlloc<A: OwnedAllocator> {
     allocator: A,
     block: *mut u8,
     next: *mut u8,
+    end: *mut u8,
     size: usize,
 }
 impl<A: OwnedAllocator> BlockAlloc<A> {
     pub unsafe fn new(mut allocator: A, size: usize) -> Self {
         assert!(size > 0);
         let block = allocator.allocate(size, PowerOfTwo::new(1));
+        let end = if block.is_null() {
+            block
+        } else {
+            block.offset(size as isize)
+        };
         return BlockAlloc {
             allocator: allocator,
             block: block,
             next: block,
+            end: end,
             size: size,
         };
     }
+    pub fn reset(&mut self) {
+        self.next = self.block;
+    }
+    pub fn remaining(&self) -> usize {
+        return distance(self.next, self.end);
+    }
+    fn is_top(&self, ptr: *mut u8, size: usize) -> bool {
+        return (ptr as usize) + size == self.next as usize;
+    }
 }
 unsafe impl<A: OwnedAllocator> OwnedAllocator for BlockAlloc<A> {
-    unsafe fn deallocate(&mut self, _ptr: *mut u8, _old_size: usize, _align: PowerOfTwo) {}
-    unsafe fn allocate(&mut self, size: usize, _align: PowerOfTwo) -> *mut u8 {
-        let ret = self.next;
-        self.next = self.next.offset(size as isize);
+    unsafe fn deallocate(&mut self, ptr: *mut u8, old_size: usize, _align: PowerOfTwo) {
+        if self.is_top(ptr, old_size) {
+            self.next = ptr;
+        }
+    }
+    unsafe fn allocate(&mut self, size: usize, align: PowerOfTwo) -> *mut u8 {
+        let ret = align.align_size(self.next as usize);
+        if ret > self.end as usize || size > (self.end as usize) - ret {
+            return null_mut();
+        }
+        self.next = (ret + size) as *mut u8;
+        return ret as *mut u8;
+    }
+    unsafe fn reallocate(&mut self, ptr: *mut u8, old_size: usize, size: usize, align: PowerOfTwo) -> *mut u8 {
+        if self.reallocate_inplace(ptr, old_size, size, align) == size {
+            return ptr;
+        }
+        let ret = self.allocate(size, align);
+        if !ret.is_null() {
+            copy_nonoverlapping(ptr, ret, cmp::min(old_size, size));
+        }
         return ret;
     }
-    unsafe fn reallocate(&mut self, _ptr: *mut u8, _old_size: usize, _size: usize, _align: PowerOfTwo) -> *mut u8 {
-        return null_mut();
+    unsafe fn reallocate_inplace(&mut self, ptr: *mut u8, old_size: usize, size: usize, _align: PowerOfTwo) -> usize {
+        if self.is_top(ptr, old_size) && size <= (self.end as usize) - (ptr as usize) {
+            self.next = ptr.offset(size as isize);
+            return size;
+        }
+        return old_size;
     }
-    unsafe fn reallocate_inplace(&mut self, _ptr: *mut u8, old_size: usize, _size: usize, _align: PowerOfTwo) -> usize {
+    unsafe fn extendable_size(&self, ptr: *mut u8, old_size: usize, _align: PowerOfTwo) -> usize {
+        if self.is_top(ptr, old_size) {
+            return (self.end as usize) - (ptr as usize);
+        }
         return old_size;
     }
 }
 impl<A: OwnedAllocator> Drop for BlockAlloc<A> {
     fn drop(&mut self) {
-        unsafe {
-            self.allocator.deallocate(self.block, self.size, PowerOfTwo::new(1));
+        if !self.block.is_null() {
+            unsafe {
+                self.allocator.deallocate(self.block, self.size, PowerOfTwo::new(1));
+            }
         }
     }
 }
 unsafe impl<A: ShareableAllocator> ShareableAllocator for BlockAlloc<A> {}
+#[test]
+fn block_alloc_test() {
+    unsafe {
+        let mut alloc = BlockAlloc::new(CheckedAlloc::new(HeapAlloc, Default::default()), 64);
+        let first = alloc.allocate(3, PowerOfTwo::new(1));
+        let second = alloc.allocate(8, PowerOfTwo::new(8));
+        assert!(PowerOfTwo::new(8).is_aligned_ptr_mut(second));
+        assert!(second >= first.offset(3));
+        assert!(alloc.allocate(64, PowerOfTwo::new(1)).is_null());
+        assert_eq!(16, alloc.reallocate_inplace(second, 8, 16, PowerOfTwo::new(8)));
+        assert_eq!(3, alloc.reallocate_inplace(first, 3, 4, PowerOfTwo::new(1)));
+        assert!(alloc.extendable_size(second, 16, PowerOfTwo::new(8)) >= 16);
+        *first = 42;
+        let moved = alloc.reallocate(first, 3, 4, PowerOfTwo::new(1));
+        assert_eq!(42, *moved);
+        alloc.deallocate(moved, 4, PowerOfTwo::new(1));
+        assert_eq!(moved, alloc.allocate(4, PowerOfTwo::new(1)));
+        alloc.reset();
+        assert_eq!(first, alloc.allocate(64, PowerOfTwo::new(1)));
+        assert!(alloc.allocate(1, PowerOfTwo::new(1)).is_null());
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::fmt;
+use std::mem;
+use std::os::raw::c_char;
+use std::os::raw::c_void;
+const MAX_FRAMES: usize = 32;
+// A frame pointer further than this above the capturing frame is taken to be garbage rather than a caller.
+const MAX_STACK_SPAN: usize = 64 << 20;
+#[cfg(unix)]
+#[repr(C)]
+struct DlInfo {
+    dli_fname: *const c_char,
+    dli_fbase: *mut c_void,
+    dli_sname: *const c_char,
+    dli_saddr: *mut c_void,
+}
+#[cfg(unix)]
+#[link(name = "dl")]
+extern "C" {
+    fn dladdr(addr: *const c_void, info: *mut DlInfo) -> i32;
+}
+#[cfg(target_arch = "x86_64")]
+#[inline(always)]
+fn frame_pointer() -> usize {
+    let fp: usize;
+    unsafe {
+        asm!("mov %rbp, $0" : "=r"(fp));
+    }
+    return fp;
+}
+#[cfg(not(target_arch = "x86_64"))]
+#[inline(always)]
+fn frame_pointer() -> usize {
+    return 0;
+}
+// The symbol containing ip and the offset into it, if the dynamic linker knows one.
+#[cfg(unix)]
+fn symbol_name(ip: usize) -> Option<(String, usize)> {
+    unsafe {
+        let mut info: DlInfo = mem::zeroed();
+        if dladdr(ip as *const c_void, &mut info) == 0 || info.dli_sname.is_null() {
+            return None;
+        }
+        let name = CStr::from_ptr(info.dli_sname).to_string_lossy().into_owned();
+        return Some((name, ip - info.dli_saddr as usize));
+    }
+}
+#[cfg(not(unix))]
+fn symbol_name(_ip: usize) -> Option<(String, usize)> {
+    return None;
+}
+// Instruction pointers only; symbols are resolved when the trace is printed. Frames are found by walking the
+// frame pointer chain, so callers outside code built with -C force-frame-pointers=yes may be cut short.
+#[derive(Clone,Eq,Hash,PartialEq)]
+pub struct StackTrace(Vec<usize>);
+impl StackTrace {
+    #[inline(never)]
+    pub fn capture() -> Self {
+        let mut frames = Vec::new();
+        // Callers' frames lie above this one; anything else ends the walk.
+        let bottom = &frames as *const Vec<usize> as usize;
+        let mut fp = frame_pointer();
+        while frames.len() < MAX_FRAMES && fp > bottom && fp - bottom < MAX_STACK_SPAN &&
+              fp % mem::align_of::<usize>() == 0 {
+            let (next, ip) = unsafe { (*(fp as *const usize), *(fp as *const usize).offset(1)) };
+            if ip == 0 {
+                break;
+            }
+            frames.push(ip);
+            if next <= fp {
+                break;
+            }
+            fp = next;
+        }
+        return StackTrace(frames);
+    }
+    pub fn frames(&self) -> &[usize] {
+        return &self.0;
+    }
+}
+impl fmt::Display for StackTrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for &ip in &self.0 {
+            match symbol_name(ip) {
+                Some((name, offset)) => try!(writeln!(f, "    {:X}: {}+{:#x}", ip, name, offset)),
+                None => try!(writeln!(f, "    {:X}: <unknown>", ip)),
+            }
+        }
+        return Ok(());
+    }
+}
+// Interns traces so that allocations made from the same place share one copy and can be grouped by id.
+pub struct StackTable {
+    traces: Vec<StackTrace>,
+    ids: HashMap<StackTrace, usize>,
+}
+impl StackTable {
+    pub fn new() -> Self {
+        return StackTable {
+            traces: Vec::new(),
+            ids: HashMap::new(),
+        };
+    }
+    pub fn intern(&mut self, trace: StackTrace) -> usize {
+        if let Some(&id) = self.ids.get(&trace) {
+            return id;
+        }
+        let id = self.traces.len();
+        self.traces.push(trace.clone());
+        self.ids.insert(trace, id);
+        return id;
+    }
+    pub fn get(&self, id: usize) -> &StackTrace {
+        return &self.traces[id];
+    }
+}
+#[test]
+fn stack_table_test() {
+    let mut table = StackTable::new();
+    let first = table.intern(StackTrace(vec![1, 2, 3]));
+    let second = table.intern(StackTrace(vec![1, 2]));
+    assert_eq!(first, table.intern(StackTrace(vec![1, 2, 3])));
+    assert!(first != second);
+    assert_eq!(&[1, 2], table.get(second).frames());
+    assert!(StackTrace::capture().frames().len() > 0);
+}
@@ -0,0 +1,239 @@
+use std::cell::Cell;
+use std::cell::RefCell;
+use std::cmp;
+use std::intrinsics;
+use std::marker;
+use std::mem;
+use std::ptr;
+use alloc::oom;
+use allocator::OwnedAllocator;
+use arena_alloc::Arena;
+use arena_alloc::ArenaOptions;
+use util;
+use util::PowerOfTwo;
+#[cfg(test)]
+use checked_alloc::CheckedAlloc;
+#[cfg(test)]
+use heap_alloc::HeapAlloc;
+#[cfg(test)]
+use std::rc::Rc;
+const START_CHUNK_BYTES: usize = 4096;
+struct TypedChunk<T> {
+    storage: *mut T,
+    capacity: usize,
+    // Initialized elements, recorded when the chunk stops being the current one.
+    entries: usize,
+}
+pub struct TypedArena<T, A: OwnedAllocator> {
+    ptr: Cell<*mut T>,
+    end: Cell<*mut T>,
+    chunks: RefCell<Vec<TypedChunk<T>>>,
+    zero_sized: Cell<usize>,
+    allocator: RefCell<A>,
+    phantom: marker::PhantomData<T>,
+}
+impl<T, A: OwnedAllocator> TypedArena<T, A> {
+    pub fn new(allocator: A) -> Self {
+        return TypedArena {
+            ptr: Cell::new(ptr::null_mut()),
+            end: Cell::new(ptr::null_mut()),
+            chunks: RefCell::new(Vec::new()),
+            zero_sized: Cell::new(0),
+            allocator: RefCell::new(allocator),
+            phantom: marker::PhantomData,
+        };
+    }
+    pub fn alloc(&self, value: T) -> &mut T {
+        unsafe {
+            if mem::size_of::<T>() == 0 {
+                self.zero_sized.set(self.zero_sized.get() + 1);
+                let ptr = PowerOfTwo::align_of::<T>().into() as *mut T;
+                ptr::write(ptr, value);
+                return &mut *ptr;
+            }
+            if self.ptr.get() == self.end.get() {
+                self.grow();
+            }
+            let ptr = self.ptr.get();
+            self.ptr.set(ptr.offset(1));
+            ptr::write(ptr, value);
+            return &mut *ptr;
+        }
+    }
+    unsafe fn grow(&self) {
+        let mut chunks = self.chunks.borrow_mut();
+        let capacity;
+        if let Some(last) = chunks.last_mut() {
+            last.entries = util::distance(last.storage, self.ptr.get());
+            capacity = last.capacity.checked_mul(2).expect("capacity overflow");
+        } else {
+            capacity = cmp::max(1, START_CHUNK_BYTES / mem::size_of::<T>());
+        }
+        let size = capacity.checked_mul(mem::size_of::<T>()).expect("capacity overflow");
+        let storage = self.allocator.borrow_mut().allocate(size, PowerOfTwo::align_of::<T>()) as *mut T;
+        if storage.is_null() {
+            oom();
+        }
+        chunks.push(TypedChunk {
+            storage: storage,
+            capacity: capacity,
+            entries: 0,
+        });
+        self.ptr.set(storage);
+        self.end.set(storage.offset(capacity as isize));
+    }
+    unsafe fn destroy_elements(&mut self) {
+        for _ in 0..self.zero_sized.get() {
+            intrinsics::drop_in_place(PowerOfTwo::align_of::<T>().into() as *mut T);
+        }
+        self.zero_sized.set(0);
+        let chunks = self.chunks.borrow();
+        let current = chunks.len().wrapping_sub(1);
+        for (index, chunk) in chunks.iter().enumerate() {
+            let entries = if index == current {
+                util::distance(chunk.storage, self.ptr.get())
+            } else {
+                chunk.entries
+            };
+            for i in 0..entries {
+                intrinsics::drop_in_place(chunk.storage.offset(i as isize));
+            }
+        }
+    }
+    unsafe fn free_chunk(&self, chunk: TypedChunk<T>) {
+        self.allocator
+            .borrow_mut()
+            .deallocate(chunk.storage as *mut u8,
+                        chunk.capacity * mem::size_of::<T>(),
+                        PowerOfTwo::align_of::<T>());
+    }
+    // Drops every element and keeps only the largest chunk for reuse.
+    pub fn clear(&mut self) {
+        unsafe {
+            self.destroy_elements();
+            let mut chunks = mem::replace(&mut *self.chunks.borrow_mut(), Vec::new());
+            if let Some(mut last) = chunks.pop() {
+                for chunk in chunks {
+                    self.free_chunk(chunk);
+                }
+                last.entries = 0;
+                self.ptr.set(last.storage);
+                self.end.set(last.storage.offset(last.capacity as isize));
+                self.chunks.borrow_mut().push(last);
+            }
+        }
+    }
+}
+impl<T, A: OwnedAllocator + Default> Default for TypedArena<T, A> {
+    fn default() -> Self {
+        return Self::new(A::default());
+    }
+}
+impl<T, A: OwnedAllocator> Drop for TypedArena<T, A> {
+    fn drop(&mut self) {
+        unsafe {
+            self.destroy_elements();
+            let chunks = mem::replace(&mut *self.chunks.borrow_mut(), Vec::new());
+            for chunk in chunks {
+                self.free_chunk(chunk);
+            }
+        }
+    }
+}
+struct Destructor {
+    ptr: *mut u8,
+    destroy: unsafe fn(*mut u8),
+}
+unsafe fn destroy<T>(ptr: *mut u8) {
+    intrinsics::drop_in_place(ptr as *mut T);
+}
+// An Arena that remembers how to drop each value it holds, for values of mixed types.
+pub struct DropArena<A: OwnedAllocator> {
+    arena: Arena<A>,
+    destructors: RefCell<Vec<Destructor>>,
+}
+impl<A: OwnedAllocator> DropArena<A> {
+    pub fn new(allocator: A, options: ArenaOptions) -> Self {
+        return DropArena {
+            arena: Arena::new(allocator, options),
+            destructors: RefCell::new(Vec::new()),
+        };
+    }
+    // T: 'static because dropck cannot see the stored values: a T borrowing something that dies before the arena
+    // would have its destructor run on freed data.
+    pub fn alloc<T: 'static>(&self, value: T) -> &mut T {
+        let ret = self.arena.alloc(value);
+        if unsafe { intrinsics::needs_drop::<T>() } {
+            self.destructors.borrow_mut().push(Destructor {
+                ptr: &mut *ret as *mut T as *mut u8,
+                destroy: destroy::<T>,
+            });
+        }
+        return ret;
+    }
+    fn destroy_all(&mut self) {
+        let destructors = mem::replace(&mut *self.destructors.borrow_mut(), Vec::new());
+        for destructor in destructors.into_iter().rev() {
+            unsafe {
+                (destructor.destroy)(destructor.ptr);
+            }
+        }
+    }
+    pub fn clear(&mut self) {
+        self.destroy_all();
+        self.arena.reset();
+    }
+}
+impl<A: OwnedAllocator + Default> Default for DropArena<A> {
+    fn default() -> Self {
+        return Self::new(A::default(), ArenaOptions::default());
+    }
+}
+impl<A: OwnedAllocator> Drop for DropArena<A> {
+    fn drop(&mut self) {
+        self.destroy_all();
+    }
+}
+#[cfg(test)]
+struct DropCounter(Rc<Cell<usize>>);
+#[cfg(test)]
+impl Drop for DropCounter {
+    fn drop(&mut self) {
+        self.0.set(self.0.get() + 1);
+    }
+}
+#[test]
+fn typed_arena_test() {
+    let count = Rc::new(Cell::new(0));
+    {
+        let mut arena = TypedArena::new(CheckedAlloc::new(HeapAlloc, Default::default()));
+        for _ in 0..1001 {
+            arena.alloc(DropCounter(count.clone()));
+        }
+        assert_eq!(0, count.get());
+        arena.clear();
+        assert_eq!(1001, count.get());
+        for _ in 0..10 {
+            arena.alloc(DropCounter(count.clone()));
+        }
+    }
+    assert_eq!(1011, count.get());
+    {
+        let arena: TypedArena<(), HeapAlloc> = Default::default();
+        arena.alloc(());
+    }
+}
+#[test]
+fn drop_arena_test() {
+    let count = Rc::new(Cell::new(0));
+    {
+        let mut arena = DropArena::new(CheckedAlloc::new(HeapAlloc, Default::default()), Default::default());
+        arena.alloc(DropCounter(count.clone()));
+        arena.alloc(1u8);
+        arena.alloc(vec![DropCounter(count.clone()), DropCounter(count.clone())]);
+        arena.clear();
+        assert_eq!(3, count.get());
+        arena.alloc(DropCounter(count.clone()));
+    }
+    assert_eq!(4, count.get());
+}